@@ -11,7 +11,12 @@ import {
   getAssociatedTokenAddressSync,
 } from "@solana/spl-token";
 import { useProgram } from "@/hooks/useProgram";
-import { getMarketPda, getOrderBookPda } from "@/lib/constants";
+import {
+  getMarketPda,
+  getBookSidePda,
+  BOOK_CAPACITY,
+  getTriggerOrdersPda,
+} from "@/lib/constants";
 import { createTokenPair, checkSolBalance } from "@/lib/tokenCreation";
 import { ArrowLeft, Loader2, Check, AlertCircle, Coins, Copy, ExternalLink } from "lucide-react";
 import Link from "next/link";
@@ -85,7 +90,6 @@ export default function CreateMarketPage() {
       }
 
       const marketPda = getMarketPda(baseMintPubkey, quoteMintPubkey);
-      const orderBookPda = getOrderBookPda(marketPda);
 
       // Get vault ATAs
       const baseVault = getAssociatedTokenAddressSync(
@@ -100,13 +104,15 @@ export default function CreateMarketPage() {
       );
 
       const tx = await (program.methods as any)
-        .createMarket(feeBpsNum)
+        .createMarket(feeBpsNum, { fifo: {} }, BOOK_CAPACITY)
         .accountsPartial({
           marketSigner: publicKey,
           baseMint: baseMintPubkey,
           quoteMint: quoteMintPubkey,
           market: marketPda,
-          orderBook: orderBookPda,
+          bids: getBookSidePda(marketPda, "bid"),
+          asks: getBookSidePda(marketPda, "ask"),
+          triggerOrders: getTriggerOrdersPda(marketPda),
           baseMintVault: baseVault,
           quoteVault: quoteVault,
           tokenProgram: TOKEN_PROGRAM_ID,
@@ -140,6 +140,8 @@ export default function TradingPage({ params }: TradingPageProps) {
           
           <TradePanel
             market={market}
+            bids={bids}
+            asks={asks}
             onOrderPlaced={refresh}
             baseSymbol="BASE"
             quoteSymbol="QUOTE"
@@ -1,17 +1,22 @@
 "use client";
 
 import { useState } from "react";
-import { useWallet } from "@solana/wallet-adapter-react";
+import { useConnection, useWallet } from "@solana/wallet-adapter-react";
 import { PublicKey } from "@solana/web3.js";
 import {
   TOKEN_PROGRAM_ID,
   getAssociatedTokenAddressSync,
 } from "@solana/spl-token";
 import { useProgram } from "@/hooks/useProgram";
-import { getOrderBookPda } from "@/lib/constants";
+import { getBookSidePda, getTriggerOrdersPda } from "@/lib/constants";
 import { MarketInfo, DisplayOrder } from "@/types";
 import { Loader2, Zap } from "lucide-react";
 
+// Trigger store layout: discriminator, market, last traded price, capacity, len, then the stops
+const STOPS_LEN_AT = 8 + 32 + 8 + 4;
+const STOPS_AT = STOPS_LEN_AT + 4;
+const STOP_SIZE = 74;
+
 interface CrankButtonProps {
   market: MarketInfo | null;
   bids: DisplayOrder[];
@@ -26,6 +31,7 @@ export function CrankButton({
   onMatched,
 }: CrankButtonProps) {
   const { publicKey } = useWallet();
+  const { connection } = useConnection();
   const { program } = useProgram();
   const [loading, setLoading] = useState(false);
   const [result, setResult] = useState<string | null>(null);
@@ -44,7 +50,6 @@ export function CrankButton({
       const baseMint = new PublicKey(market.baseMint);
       const quoteMint = new PublicKey(market.quoteMint);
       const marketPda = new PublicKey(market.address);
-      const orderBookPda = getOrderBookPda(marketPda);
 
       const baseVault = getAssociatedTokenAddressSync(
         baseMint,
@@ -71,26 +76,43 @@ export function CrankButton({
         isWritable: boolean;
       }[] = [];
 
-      // For each matchable bid, add bidder's base ATA
+      const addAta = (mint: PublicKey, owner: string) => {
+        const ata = getAssociatedTokenAddressSync(mint, new PublicKey(owner));
+        if (!remainingAccounts.some((account) => account.pubkey.equals(ata))) {
+          remainingAccounts.push({
+            pubkey: ata,
+            isSigner: false,
+            isWritable: true,
+          });
+        }
+      };
+
+      // Bidders receive base, and quote back when they fill below their price or expire
       for (const bid of bids) {
-        const bidder = new PublicKey(bid.owner);
-        const bidderBaseAta = getAssociatedTokenAddressSync(baseMint, bidder);
-        remainingAccounts.push({
-          pubkey: bidderBaseAta,
-          isSigner: false,
-          isWritable: true,
-        });
+        addAta(baseMint, bid.owner);
+        addAta(quoteMint, bid.owner);
       }
 
-      // For each matchable ask, add asker's quote ATA
+      // Askers receive quote, and base back when they expire
       for (const ask of asks) {
-        const asker = new PublicKey(ask.owner);
-        const askerQuoteAta = getAssociatedTokenAddressSync(quoteMint, asker);
-        remainingAccounts.push({
-          pubkey: askerQuoteAta,
-          isSigner: false,
-          isWritable: true,
-        });
+        addAta(quoteMint, ask.owner);
+        addAta(baseMint, ask.owner);
+      }
+
+      // Stop orders fired by the fills pay out to their owners
+      const triggerOrders = await connection.getAccountInfo(
+        getTriggerOrdersPda(marketPda)
+      );
+      if (triggerOrders) {
+        const stops = triggerOrders.data.readUInt32LE(STOPS_LEN_AT);
+        for (let i = 0; i < stops; i++) {
+          const at = STOPS_AT + i * STOP_SIZE;
+          const owner = new PublicKey(
+            triggerOrders.data.subarray(at, at + 32)
+          ).toBase58();
+          addAta(baseMint, owner);
+          addAta(quoteMint, owner);
+        }
       }
 
       // Add cranker's quote ATA for fee
@@ -113,7 +135,9 @@ export function CrankButton({
           baseMintVault: baseVault,
           quoteMintVault: quoteVault,
           market: marketPda,
-          orderBook: orderBookPda,
+          bids: getBookSidePda(marketPda, "bid"),
+          asks: getBookSidePda(marketPda, "ask"),
+          triggerOrders: getTriggerOrdersPda(marketPda),
           feeCollector: feeCollector,
           tokenProgram: TOKEN_PROGRAM_ID,
         })
@@ -9,7 +9,7 @@ import {
   getAssociatedTokenAddressSync,
 } from "@solana/spl-token";
 import { useProgram } from "@/hooks/useProgram";
-import { getOrderBookPda } from "@/lib/constants";
+import { getBookSidePda } from "@/lib/constants";
 import { DisplayOrder, MarketInfo } from "@/types";
 import { Loader2, X } from "lucide-react";
 
@@ -57,7 +57,6 @@ export function MyOrders({
       const baseMint = new PublicKey(market.baseMint);
       const quoteMint = new PublicKey(market.quoteMint);
       const marketPda = new PublicKey(market.address);
-      const orderBookPda = getOrderBookPda(marketPda);
 
       const baseVault = getAssociatedTokenAddressSync(
         baseMint,
@@ -77,7 +76,9 @@ export function MyOrders({
       );
 
       const sideArg = order.side === "bid" ? { bid: {} } : { ask: {} };
+      const isBid = order.side === "bid";
 
+      // A bid refunds quote, an ask refunds base
       await (program.methods as any)
         .cancelOrder(sideArg, new BN(order.orderId))
         .accountsPartial({
@@ -85,11 +86,12 @@ export function MyOrders({
           market: marketPda,
           baseMint: baseMint,
           quoteMint: quoteMint,
-          orderBook: orderBookPda,
-          baseMintVault: baseVault,
-          quoteMintVault: quoteVault,
-          traderBaseMintAccount: traderBaseAta,
-          traderQuoteMintAccount: traderQuoteAta,
+          bids: isBid ? getBookSidePda(marketPda, "bid") : null,
+          asks: isBid ? null : getBookSidePda(marketPda, "ask"),
+          baseMintVault: isBid ? null : baseVault,
+          quoteMintVault: isBid ? quoteVault : null,
+          traderBaseMintAccount: isBid ? null : traderBaseAta,
+          traderQuoteMintAccount: isBid ? traderQuoteAta : null,
           tokenProgram: TOKEN_PROGRAM_ID,
         })
         .rpc();
@@ -4,26 +4,30 @@ import { useState, useEffect } from "react";
 import { useWallet } from "@solana/wallet-adapter-react";
 import { useConnection } from "@solana/wallet-adapter-react";
 import { PublicKey, SystemProgram, Transaction } from "@solana/web3.js";
+import BN from "bn.js";
 import {
-  TOKEN_PROGRAM_ID,
   ASSOCIATED_TOKEN_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
   getAssociatedTokenAddressSync,
   createAssociatedTokenAccountInstruction,
   getAccount,
 } from "@solana/spl-token";
 import { useProgram } from "@/hooks/useProgram";
 import {
-  getOrderBookPda,
+  getBookSidePda,
+  getTriggerOrdersPda,
   toRawAmount,
   BASE_DECIMALS,
   QUOTE_DECIMALS,
 } from "@/lib/constants";
-import { MarketInfo } from "@/types";
+import { DisplayOrder, MarketInfo } from "@/types";
 import { Loader2, AlertTriangle } from "lucide-react";
 import { fromRawAmount } from "@/lib/constants";
 
 interface TradePanelProps {
   market: MarketInfo | null;
+  bids: DisplayOrder[];
+  asks: DisplayOrder[];
   onOrderPlaced: () => void;
   baseSymbol?: string;
   quoteSymbol?: string;
@@ -31,6 +35,8 @@ interface TradePanelProps {
 
 export function TradePanel({
   market,
+  bids,
+  asks,
   onOrderPlaced,
   baseSymbol = "BASE",
   quoteSymbol = "QUOTE",
@@ -42,6 +48,7 @@ export function TradePanel({
   const [side, setSide] = useState<"buy" | "sell">("buy");
   const [price, setPrice] = useState("");
   const [amount, setAmount] = useState("");
+  const [postWithoutMatching, setPostWithoutMatching] = useState(false);
   const [loading, setLoading] = useState(false);
   const [error, setError] = useState<string | null>(null);
   const [balances, setBalances] = useState<{
@@ -120,6 +127,107 @@ export function TradePanel({
     }
   };
 
+  // The makers it can fill against are paid through their ATAs
+  const placeOrder = async (
+    sideArg: object,
+    rawPrice: BN,
+    rawAmount: BN,
+    priceNum: number,
+    marketPda: PublicKey,
+    baseMint: PublicKey,
+    quoteMint: PublicKey,
+    baseVault: PublicKey,
+    quoteVault: PublicKey,
+    traderBaseAta: PublicKey,
+    traderQuoteAta: PublicKey
+  ): Promise<string> => {
+    const crossing =
+      side === "buy"
+        ? asks.filter((ask) => ask.price <= priceNum)
+        : bids.filter((bid) => bid.price >= priceNum);
+    const makerAtas: PublicKey[] = [];
+    for (const order of crossing) {
+      for (const mint of [baseMint, quoteMint]) {
+        const ata = getAssociatedTokenAddressSync(
+          mint,
+          new PublicKey(order.owner)
+        );
+        if (!makerAtas.some((known) => known.equals(ata))) {
+          makerAtas.push(ata);
+        }
+      }
+    }
+
+    return (program!.methods as any)
+      .placeOrder(
+        rawPrice,
+        rawAmount,
+        sideArg,
+        { goodTillCancel: {} },
+        { disabled: {} },
+        new BN(0),
+        new BN(0),
+        { cancelTaker: {} },
+        new BN(0),
+        new BN(0),
+        { fixed: {} },
+        new BN(0)
+      )
+      .accountsPartial({
+        trader: publicKey,
+        market: marketPda,
+        baseMint: baseMint,
+        quoteMint: quoteMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        baseMintVault: baseVault,
+        quoteMintVault: quoteVault,
+        traderBaseMintAccount: traderBaseAta,
+        traderQuoteMintAccount: traderQuoteAta,
+        bids: getBookSidePda(marketPda, "bid"),
+        asks: getBookSidePda(marketPda, "ask"),
+        triggerOrders: getTriggerOrdersPda(marketPda),
+        feeCollector: getAssociatedTokenAddressSync(
+          quoteMint,
+          new PublicKey(market!.creator)
+        ),
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts(
+        makerAtas.map((pubkey) => ({
+          pubkey,
+          isSigner: false,
+          isWritable: true,
+        }))
+      )
+      .rpc();
+  };
+
+  // A crossing post rests until the crank fills it
+  const postOrder = async (
+    sideArg: object,
+    rawPrice: BN,
+    rawAmount: BN,
+    marketPda: PublicKey,
+    baseMint: PublicKey,
+    quoteMint: PublicKey,
+    vault: PublicKey,
+    traderTokenAccount: PublicKey
+  ): Promise<string> =>
+    (program!.methods as any)
+      .postOrder(sideArg, rawPrice, rawAmount, new BN(0))
+      .accountsPartial({
+        trader: publicKey,
+        market: marketPda,
+        baseMint: baseMint,
+        quoteMint: quoteMint,
+        bookSide: getBookSidePda(marketPda, side === "buy" ? "bid" : "ask"),
+        vault,
+        traderTokenAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
   const handleSubmit = async (e: React.FormEvent) => {
     e.preventDefault();
     if (!program || !publicKey || !market) return;
@@ -138,7 +246,6 @@ export function TradePanel({
       const baseMint = new PublicKey(market.baseMint);
       const quoteMint = new PublicKey(market.quoteMint);
       const marketPda = new PublicKey(market.address);
-      const orderBookPda = getOrderBookPda(marketPda);
 
       // Get vaults
       const baseVault = getAssociatedTokenAddressSync(
@@ -193,23 +300,30 @@ export function TradePanel({
 
       const sideArg = side === "buy" ? { bid: {} } : { ask: {} };
 
-      const tx = await (program.methods as any)
-        .placeOrder(rawPrice, rawAmount, sideArg)
-        .accountsPartial({
-          trader: publicKey,
-          market: marketPda,
-          baseMint: baseMint,
-          quoteMint: quoteMint,
-          tokenProgram: TOKEN_PROGRAM_ID,
-          baseMintVault: baseVault,
-          quoteMintVault: quoteVault,
-          traderBaseMintAccount: traderBaseAta,
-          traderQuoteMintAccount: traderQuoteAta,
-          orderBook: orderBookPda,
-          systemProgram: SystemProgram.programId,
-          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
-        })
-        .rpc();
+      const tx = postWithoutMatching
+        ? await postOrder(
+            sideArg,
+            rawPrice,
+            rawAmount,
+            marketPda,
+            baseMint,
+            quoteMint,
+            side === "buy" ? quoteVault : baseVault,
+            side === "buy" ? traderQuoteAta : traderBaseAta
+          )
+        : await placeOrder(
+            sideArg,
+            rawPrice,
+            rawAmount,
+            priceNum,
+            marketPda,
+            baseMint,
+            quoteMint,
+            baseVault,
+            quoteVault,
+            traderBaseAta,
+            traderQuoteAta
+          );
 
       console.log("Order placed:", tx);
       setPrice("");
@@ -305,6 +419,16 @@ export function TradePanel({
             />
           </div>
 
+          <label className="flex items-center gap-2 text-xs text-zinc-500 cursor-pointer">
+            <input
+              type="checkbox"
+              checked={postWithoutMatching}
+              onChange={(e) => setPostWithoutMatching(e.target.checked)}
+              className="accent-blue-500"
+            />
+            Post without matching
+          </label>
+
           {price && amount && (
             <div className="flex justify-between items-center py-2 px-3 bg-[#1e1e2e] rounded-lg text-sm">
               <span className="text-zinc-500">Total</span>
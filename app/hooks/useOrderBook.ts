@@ -3,14 +3,126 @@
 import { useState, useEffect, useCallback } from "react";
 import { useConnection } from "@solana/wallet-adapter-react";
 import { PublicKey } from "@solana/web3.js";
-import { useProgram } from "./useProgram";
+import BN from "bn.js";
 import {
-  getOrderBookPda,
+  getBookSidePda,
   fromRawAmount,
   BASE_DECIMALS,
   QUOTE_DECIMALS,
 } from "@/lib/constants";
-import { DisplayOrder } from "@/types";
+import { DisplayOrder, Side } from "@/types";
+
+// Byte layout of a book side account (`OrderBookSide` in the program): a header, then slots
+// of a leaf (16-byte key, then the order) and an inner node for each of the four trees. The
+// IDL does not describe the slots
+const SIDE_HEADER_SIZE = 8 + 32 + 8 + 4 * 3 + 4 * 4 + 8 * 3;
+const LEN_OFFSET = 8 + 32 + 8 + 4;
+const ORDER_SIZE = 106;
+const SLOT_SIZE = 16 + ORDER_SIZE + 4 * 9;
+// Where an order's `peg` byte sits, followed by its signed `peg_offset`
+const PEG_AT = 97;
+
+interface RawOrder {
+  key: BN;
+  owner: string;
+  price: BN;
+  amount: BN;
+  orderId: BN;
+  peg: number;
+  pegOffset: BN;
+}
+
+// Orders sit in the first `len` slots in no particular order
+const decodeSide = (data: Buffer): RawOrder[] => {
+  const len = data.readUInt32LE(LEN_OFFSET);
+  const orders = [];
+  for (let i = 0; i < len; i++) {
+    const slot = SIDE_HEADER_SIZE + i * SLOT_SIZE;
+    const order = slot + 16;
+    const u64 = (offset: number) =>
+      new BN(data.subarray(order + offset, order + offset + 8), "le");
+    orders.push({
+      key: new BN(data.subarray(slot, order), "le"),
+      owner: new PublicKey(data.subarray(order, order + 32)).toBase58(),
+      price: u64(32),
+      amount: u64(40),
+      orderId: u64(48),
+      peg: data[order + PEG_AT],
+      pegOffset: u64(PEG_AT + 1).fromTwos(64),
+    });
+  }
+  return orders;
+};
+
+// Best fixed-price order of a side, what pegged orders follow
+const bestFixed = (orders: RawOrder[], side: Side): BN | null =>
+  orders
+    .filter((order) => order.peg === 0)
+    .map((order) => order.price)
+    .reduce<BN | null>(
+      (best, price) =>
+        best === null || (side === "bid" ? price.gt(best) : price.lt(best))
+          ? price
+          : best,
+      null
+    );
+
+// Price an order trades at now, as `PegReferences::price_of` in the program works it out: a
+// pegged order sits at its offset from the best fixed bid, ask or their mid, never beyond its
+// limit. Null for a pegged order whose reference is missing or that would go below 0
+const priceOf = (
+  order: RawOrder,
+  side: Side,
+  bestBid: BN | null,
+  bestAsk: BN | null
+): BN | null => {
+  let reference: BN | null;
+  switch (order.peg) {
+    case 0:
+      return order.price;
+    case 1:
+      reference = bestBid;
+      break;
+    case 2:
+      reference = bestAsk;
+      break;
+    default:
+      reference = bestBid && bestAsk ? bestBid.add(bestAsk).divn(2) : null;
+  }
+  if (reference === null) return null;
+
+  const offset = reference.add(order.pegOffset);
+  if (offset.isNeg()) return null;
+  const pegged = BN.max(offset, new BN(1));
+  return side === "bid"
+    ? BN.min(pegged, order.price)
+    : BN.max(pegged, order.price);
+};
+
+// Best price first on both sides, then in order of arrival
+const toDisplay = (
+  orders: RawOrder[],
+  side: Side,
+  bestBid: BN | null,
+  bestAsk: BN | null
+): DisplayOrder[] =>
+  orders
+    .map((order) => ({ order, price: priceOf(order, side, bestBid, bestAsk) }))
+    .filter(
+      (priced): priced is { order: RawOrder; price: BN } => priced.price !== null
+    )
+    .sort(
+      (a, b) =>
+        (side === "bid" ? b.price.cmp(a.price) : a.price.cmp(b.price)) ||
+        a.order.key.cmp(b.order.key)
+    )
+    .map(({ order, price }) => ({
+      owner: order.owner,
+      price: fromRawAmount(price, QUOTE_DECIMALS),
+      amount: fromRawAmount(order.amount, BASE_DECIMALS),
+      orderId: order.orderId.toString(),
+      side,
+    }));
 
 interface UseOrderBookResult {
   bids: DisplayOrder[];
@@ -22,14 +134,13 @@ interface UseOrderBookResult {
 
 export function useOrderBook(marketAddress: string | null): UseOrderBookResult {
   const { connection } = useConnection();
-  const { program } = useProgram();
   const [bids, setBids] = useState<DisplayOrder[]>([]);
   const [asks, setAsks] = useState<DisplayOrder[]>([]);
   const [loading, setLoading] = useState(false);
   const [error, setError] = useState<string | null>(null);
 
   const fetchOrderBook = useCallback(async () => {
-    if (!program || !marketAddress) {
+    if (!marketAddress) {
       setBids([]);
       setAsks([]);
       return;
@@ -40,42 +151,28 @@ export function useOrderBook(marketAddress: string | null): UseOrderBookResult {
 
     try {
       const marketPubkey = new PublicKey(marketAddress);
-      const orderBookPda = getOrderBookPda(marketPubkey);
-
-      const orderBook = await (program.account as any).orderBook.fetch(
-        orderBookPda
-      );
-
-      // Convert raw orders to display format
-      const displayBids: DisplayOrder[] = orderBook.bids.map((order: any) => ({
-        owner: order.owner.toBase58(),
-        price: fromRawAmount(BigInt(order.price.toString()), QUOTE_DECIMALS),
-        amount: fromRawAmount(BigInt(order.amount.toString()), BASE_DECIMALS),
-        orderId: order.orderId.toString(),
-        side: "bid" as const,
-      }));
-
-      const displayAsks: DisplayOrder[] = orderBook.asks.map((order: any) => ({
-        owner: order.owner.toBase58(),
-        price: fromRawAmount(BigInt(order.price.toString()), QUOTE_DECIMALS),
-        amount: fromRawAmount(BigInt(order.amount.toString()), BASE_DECIMALS),
-        orderId: order.orderId.toString(),
-        side: "ask" as const,
-      }));
-
-      // Sort: bids descending, asks ascending
-      displayBids.sort((a, b) => b.price - a.price);
-      displayAsks.sort((a, b) => a.price - b.price);
-
-      setBids(displayBids);
-      setAsks(displayAsks);
+      const [bidsAccount, asksAccount] =
+        await connection.getMultipleAccountsInfo([
+          getBookSidePda(marketPubkey, "bid"),
+          getBookSidePda(marketPubkey, "ask"),
+        ]);
+      if (!bidsAccount || !asksAccount) {
+        throw new Error("Order book not found");
+      }
+
+      const rawBids = decodeSide(bidsAccount.data);
+      const rawAsks = decodeSide(asksAccount.data);
+      const bestBid = bestFixed(rawBids, "bid");
+      const bestAsk = bestFixed(rawAsks, "ask");
+      setBids(toDisplay(rawBids, "bid", bestBid, bestAsk));
+      setAsks(toDisplay(rawAsks, "ask", bestBid, bestAsk));
     } catch (err: any) {
       console.error("Error fetching orderbook:", err);
       setError(err.message || "Failed to fetch orderbook");
     } finally {
       setLoading(false);
     }
-  }, [program, marketAddress]);
+  }, [connection, marketAddress]);
 
   // Initial fetch
   useEffect(() => {
@@ -1,5 +1,6 @@
 import { PublicKey } from "@solana/web3.js";
 import BN from "bn.js";
+import { Side } from "@/types";
 
 export const PROGRAM_ID = new PublicKey(
   "Gvv7atyen9oY1TccNVDb76btjUKwXi6bgmhQZDnaryGg"
@@ -11,6 +12,9 @@ export const CLUSTER = "https://api.devnet.solana.com"; // devnet
 export const BASE_DECIMALS = 9;
 export const QUOTE_DECIMALS = 6;
 
+// Orders each book side holds; a side account stays within the 10 KiB an instruction can allocate
+export const BOOK_CAPACITY = 64;
+
 // Convert display amount to raw units (returns BN for Anchor)
 export const toRawAmount = (amount: number, decimals: number): BN => {
   return new BN(Math.floor(amount * Math.pow(10, decimals)));
@@ -40,9 +44,20 @@ export const getMarketPda = (
   return pda;
 };
 
-export const getOrderBookPda = (market: PublicKey): PublicKey => {
+export const getBookSidePda = (market: PublicKey, side: Side): PublicKey => {
+  const [pda] = PublicKey.findProgramAddressSync(
+    [
+      Buffer.from(side === "bid" ? "market_bids" : "market_asks"),
+      market.toBuffer(),
+    ],
+    PROGRAM_ID
+  );
+  return pda;
+};
+
+export const getTriggerOrdersPda = (market: PublicKey): PublicKey => {
   const [pda] = PublicKey.findProgramAddressSync(
-    [Buffer.from("market_orderBook"), market.toBuffer()],
+    [Buffer.from("market_triggerOrders"), market.toBuffer()],
     PROGRAM_ID
   );
   return pda;
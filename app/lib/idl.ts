@@ -12,14 +12,15 @@ export type OrderbookDex = {
       discriminator: [95, 129, 237, 240, 8, 49, 223, 132];
       accounts: [
         { name: "trader"; writable: true; signer: true },
-        { name: "market"; writable: true },
-        { name: "base_mint"; writable: true },
-        { name: "quote_mint"; writable: true },
-        { name: "order_book"; writable: true },
-        { name: "base_mint_vault"; writable: true },
-        { name: "quote_mint_vault"; writable: true },
-        { name: "trader_base_mint_account"; writable: true },
-        { name: "trader_quote_mint_account"; writable: true },
+        { name: "market" },
+        { name: "base_mint" },
+        { name: "quote_mint" },
+        { name: "bids"; writable: true; optional: true },
+        { name: "asks"; writable: true; optional: true },
+        { name: "base_mint_vault"; writable: true; optional: true },
+        { name: "quote_mint_vault"; writable: true; optional: true },
+        { name: "trader_base_mint_account"; writable: true; optional: true },
+        { name: "trader_quote_mint_account"; writable: true; optional: true },
         { name: "token_program" }
       ];
       args: [
@@ -35,14 +36,20 @@ export type OrderbookDex = {
         { name: "base_mint"; writable: true },
         { name: "quote_mint"; writable: true },
         { name: "market"; writable: true },
-        { name: "order_book"; writable: true },
+        { name: "bids"; writable: true },
+        { name: "asks"; writable: true },
+        { name: "trigger_orders"; writable: true },
         { name: "base_mint_vault"; writable: true },
         { name: "quote_vault"; writable: true },
         { name: "token_program" },
         { name: "associated_token_program" },
         { name: "system_program" }
       ];
-      args: [{ name: "fee_bps"; type: "u16" }];
+      args: [
+        { name: "fee_bps"; type: "u16" },
+        { name: "matching"; type: { defined: { name: "MatchingAlgorithm" } } },
+        { name: "capacity"; type: "u32" }
+      ];
     },
     {
       name: "match_order";
@@ -53,8 +60,10 @@ export type OrderbookDex = {
         { name: "quote_mint" },
         { name: "base_mint_vault"; writable: true },
         { name: "quote_mint_vault"; writable: true },
-        { name: "market"; writable: true },
-        { name: "order_book"; writable: true },
+        { name: "market" },
+        { name: "bids"; writable: true },
+        { name: "asks"; writable: true },
+        { name: "trigger_orders"; writable: true },
         { name: "fee_collector"; writable: true },
         { name: "token_program" }
       ];
@@ -65,28 +74,66 @@ export type OrderbookDex = {
       discriminator: [51, 194, 155, 175, 109, 130, 96, 106];
       accounts: [
         { name: "trader"; writable: true; signer: true },
-        { name: "market"; writable: true },
-        { name: "base_mint"; writable: true },
-        { name: "quote_mint"; writable: true },
+        { name: "market" },
+        { name: "base_mint" },
+        { name: "quote_mint" },
         { name: "token_program" },
         { name: "base_mint_vault"; writable: true },
         { name: "quote_mint_vault"; writable: true },
         { name: "trader_base_mint_account"; writable: true },
         { name: "trader_quote_mint_account"; writable: true },
-        { name: "order_book"; writable: true },
+        { name: "bids"; writable: true },
+        { name: "asks"; writable: true },
+        { name: "trigger_orders"; writable: true },
+        { name: "fee_collector"; writable: true },
         { name: "system_program" },
         { name: "associated_token_program" }
       ];
       args: [
         { name: "price"; type: "u64" },
         { name: "amount"; type: "u64" },
-        { name: "side"; type: { defined: { name: "Side" } } }
+        { name: "side"; type: { defined: { name: "Side" } } },
+        { name: "time_in_force"; type: { defined: { name: "TimeInForce" } } },
+        { name: "post_only"; type: { defined: { name: "PostOnly" } } },
+        { name: "expires_at"; type: "i64" },
+        { name: "client_order_id"; type: "u64" },
+        {
+          name: "self_trade_behavior";
+          type: { defined: { name: "SelfTradeBehavior" } };
+        },
+        { name: "display_amount"; type: "u64" },
+        { name: "linked_order_id"; type: "u64" },
+        { name: "peg"; type: { defined: { name: "Peg" } } },
+        { name: "peg_offset"; type: "i64" }
+      ];
+    },
+    {
+      name: "post_order";
+      discriminator: [241, 172, 254, 140, 77, 72, 246, 132];
+      accounts: [
+        { name: "trader"; writable: true; signer: true },
+        { name: "market" },
+        { name: "base_mint" },
+        { name: "quote_mint" },
+        { name: "book_side"; writable: true },
+        { name: "vault"; writable: true },
+        { name: "trader_token_account"; writable: true },
+        { name: "token_program" }
+      ];
+      args: [
+        { name: "side"; type: { defined: { name: "Side" } } },
+        { name: "price"; type: "u64" },
+        { name: "amount"; type: "u64" },
+        { name: "expires_at"; type: "i64" }
       ];
     }
   ];
   accounts: [
     { name: "Market"; discriminator: [219, 190, 213, 55, 0, 227, 198, 154] },
-    { name: "OrderBook"; discriminator: [55, 230, 125, 218, 149, 39, 65, 248] }
+    {
+      name: "OrderBookSide";
+      discriminator: [115, 154, 88, 152, 139, 105, 181, 88];
+    }
   ];
   errors: [
     { code: 6000; name: "OrderBookFull"; msg: "OrderBook is full" },
@@ -97,21 +144,55 @@ export type OrderbookDex = {
     },
     { code: 6002; name: "ErrorValueInvalid"; msg: "Enter Valid Value" },
     { code: 6003; name: "InvalidAta"; msg: "The Ata is invalid" },
-    { code: 6004; name: "AtaNotFound"; msg: "The Ata not found" }
-  ];
-  types: [
+    { code: 6004; name: "AtaNotFound"; msg: "The Ata not found" },
     {
-      name: "LimitOrder";
-      type: {
-        kind: "struct";
-        fields: [
-          { name: "owner"; type: "pubkey" },
-          { name: "price"; type: "u64" },
-          { name: "amount"; type: "u64" },
-          { name: "order_id"; type: "u64" }
-        ];
-      };
+      code: 6005;
+      name: "InsufficientLiquidity";
+      msg: "Not enough liquidity to fill the whole order";
+    },
+    {
+      code: 6006;
+      name: "PostOnlyWouldCross";
+      msg: "Post-only order would cross the spread";
     },
+    { code: 6007; name: "OrderNotFound"; msg: "Order not found" },
+    {
+      code: 6008;
+      name: "DuplicateClientOrderId";
+      msg: "Client order id is already used by a resting order";
+    },
+    {
+      code: 6009;
+      name: "BatchOrderWouldCross";
+      msg: "Batch order would cross the spread";
+    },
+    {
+      code: 6010;
+      name: "TriggerOrdersFull";
+      msg: "Trigger order store is full";
+    },
+    {
+      code: 6011;
+      name: "OrderAlreadyLinked";
+      msg: "Order is already part of a one-cancels-other pair";
+    },
+    {
+      code: 6012;
+      name: "PeggedOrderWouldCross";
+      msg: "Pegged order would cross the spread";
+    },
+    {
+      code: 6013;
+      name: "SlippageExceeded";
+      msg: "Swap output is below the minimum";
+    },
+    {
+      code: 6014;
+      name: "SideAccountsMissing";
+      msg: "The book side or vault of a side the instruction touches was not passed";
+    }
+  ];
+  types: [
     {
       name: "Market";
       type: {
@@ -122,27 +203,94 @@ export type OrderbookDex = {
           { name: "base_vault"; type: "pubkey" },
           { name: "quote_vault"; type: "pubkey" },
           { name: "fee_bps"; type: "u16" },
-          { name: "creator"; type: "pubkey" }
+          { name: "creator"; type: "pubkey" },
+          {
+            name: "matching";
+            type: { defined: { name: "MatchingAlgorithm" } };
+          }
+        ];
+      };
+    },
+    {
+      name: "MatchingAlgorithm";
+      type: {
+        kind: "enum";
+        variants: [
+          { name: "Fifo" },
+          {
+            name: "ProRata";
+            fields: [{ name: "top_order_priority"; type: "bool" }];
+          }
         ];
       };
     },
     {
-      name: "OrderBook";
+      name: "OrderBookSide";
+      serialization: "bytemuck";
+      repr: { kind: "c"; packed: true };
       type: {
         kind: "struct";
         fields: [
           { name: "market"; type: "pubkey" },
           { name: "next_order_id"; type: "u64" },
-          { name: "bids"; type: { vec: { defined: { name: "LimitOrder" } } } },
-          { name: "asks"; type: { vec: { defined: { name: "LimitOrder" } } } }
+          { name: "capacity"; type: "u32" },
+          { name: "len"; type: "u32" },
+          { name: "pegged"; type: "u32" },
+          { name: "roots"; type: { array: ["u32", 4] } },
+          { name: "next_sequence"; type: "u64" },
+          { name: "priced_from_bid"; type: "u64" },
+          { name: "priced_from_ask"; type: "u64" }
+        ];
+      };
+    },
+    {
+      name: "Peg";
+      type: {
+        kind: "enum";
+        variants: [
+          { name: "Fixed" },
+          { name: "BestBid" },
+          { name: "BestAsk" },
+          { name: "Mid" }
+        ];
+      };
+    },
+    {
+      name: "PostOnly";
+      type: {
+        kind: "enum";
+        variants: [
+          { name: "Disabled" },
+          { name: "Reject" },
+          { name: "Slide" }
+        ];
+      };
+    },
+    {
+      name: "SelfTradeBehavior";
+      type: {
+        kind: "enum";
+        variants: [
+          { name: "CancelTaker" },
+          { name: "CancelMaker" },
+          { name: "CancelBoth" },
+          { name: "DecrementAndCancel" }
         ];
       };
     },
     {
       name: "Side";
+      type: { kind: "enum"; variants: [{ name: "Bid" }, { name: "Ask" }] };
+    },
+    {
+      name: "TimeInForce";
       type: {
         kind: "enum";
-        variants: [{ name: "Bid" }, { name: "Ask" }];
+        variants: [
+          { name: "GoodTillCancel" },
+          { name: "ImmediateOrCancel" },
+          { name: "FillOrKill" }
+        ];
       };
     }
   ];
@@ -162,14 +310,15 @@ export const IDL: OrderbookDex = {
       discriminator: [95, 129, 237, 240, 8, 49, 223, 132],
       accounts: [
         { name: "trader", writable: true, signer: true },
-        { name: "market", writable: true },
-        { name: "base_mint", writable: true },
-        { name: "quote_mint", writable: true },
-        { name: "order_book", writable: true },
-        { name: "base_mint_vault", writable: true },
-        { name: "quote_mint_vault", writable: true },
-        { name: "trader_base_mint_account", writable: true },
-        { name: "trader_quote_mint_account", writable: true },
+        { name: "market" },
+        { name: "base_mint" },
+        { name: "quote_mint" },
+        { name: "bids", writable: true, optional: true },
+        { name: "asks", writable: true, optional: true },
+        { name: "base_mint_vault", writable: true, optional: true },
+        { name: "quote_mint_vault", writable: true, optional: true },
+        { name: "trader_base_mint_account", writable: true, optional: true },
+        { name: "trader_quote_mint_account", writable: true, optional: true },
         { name: "token_program" },
       ],
       args: [
@@ -185,14 +334,20 @@ export const IDL: OrderbookDex = {
         { name: "base_mint", writable: true },
         { name: "quote_mint", writable: true },
         { name: "market", writable: true },
-        { name: "order_book", writable: true },
+        { name: "bids", writable: true },
+        { name: "asks", writable: true },
+        { name: "trigger_orders", writable: true },
         { name: "base_mint_vault", writable: true },
         { name: "quote_vault", writable: true },
         { name: "token_program" },
         { name: "associated_token_program" },
         { name: "system_program" },
       ],
-      args: [{ name: "fee_bps", type: "u16" }],
+      args: [
+        { name: "fee_bps", type: "u16" },
+        { name: "matching", type: { defined: { name: "MatchingAlgorithm" } } },
+        { name: "capacity", type: "u32" },
+      ],
     },
     {
       name: "match_order",
@@ -203,8 +358,10 @@ export const IDL: OrderbookDex = {
         { name: "quote_mint" },
         { name: "base_mint_vault", writable: true },
         { name: "quote_mint_vault", writable: true },
-        { name: "market", writable: true },
-        { name: "order_book", writable: true },
+        { name: "market" },
+        { name: "bids", writable: true },
+        { name: "asks", writable: true },
+        { name: "trigger_orders", writable: true },
         { name: "fee_collector", writable: true },
         { name: "token_program" },
       ],
@@ -215,15 +372,18 @@ export const IDL: OrderbookDex = {
       discriminator: [51, 194, 155, 175, 109, 130, 96, 106],
       accounts: [
         { name: "trader", writable: true, signer: true },
-        { name: "market", writable: true },
-        { name: "base_mint", writable: true },
-        { name: "quote_mint", writable: true },
+        { name: "market" },
+        { name: "base_mint" },
+        { name: "quote_mint" },
         { name: "token_program" },
         { name: "base_mint_vault", writable: true },
         { name: "quote_mint_vault", writable: true },
         { name: "trader_base_mint_account", writable: true },
         { name: "trader_quote_mint_account", writable: true },
-        { name: "order_book", writable: true },
+        { name: "bids", writable: true },
+        { name: "asks", writable: true },
+        { name: "trigger_orders", writable: true },
+        { name: "fee_collector", writable: true },
         { name: "system_program" },
         { name: "associated_token_program" },
       ],
@@ -231,12 +391,47 @@ export const IDL: OrderbookDex = {
         { name: "price", type: "u64" },
         { name: "amount", type: "u64" },
         { name: "side", type: { defined: { name: "Side" } } },
+        { name: "time_in_force", type: { defined: { name: "TimeInForce" } } },
+        { name: "post_only", type: { defined: { name: "PostOnly" } } },
+        { name: "expires_at", type: "i64" },
+        { name: "client_order_id", type: "u64" },
+        {
+          name: "self_trade_behavior",
+          type: { defined: { name: "SelfTradeBehavior" } },
+        },
+        { name: "display_amount", type: "u64" },
+        { name: "linked_order_id", type: "u64" },
+        { name: "peg", type: { defined: { name: "Peg" } } },
+        { name: "peg_offset", type: "i64" },
+      ],
+    },
+    {
+      name: "post_order",
+      discriminator: [241, 172, 254, 140, 77, 72, 246, 132],
+      accounts: [
+        { name: "trader", writable: true, signer: true },
+        { name: "market" },
+        { name: "base_mint" },
+        { name: "quote_mint" },
+        { name: "book_side", writable: true },
+        { name: "vault", writable: true },
+        { name: "trader_token_account", writable: true },
+        { name: "token_program" },
+      ],
+      args: [
+        { name: "side", type: { defined: { name: "Side" } } },
+        { name: "price", type: "u64" },
+        { name: "amount", type: "u64" },
+        { name: "expires_at", type: "i64" },
       ],
     },
   ],
   accounts: [
     { name: "Market", discriminator: [219, 190, 213, 55, 0, 227, 198, 154] },
-    { name: "OrderBook", discriminator: [55, 230, 125, 218, 149, 39, 65, 248] },
+    {
+      name: "OrderBookSide",
+      discriminator: [115, 154, 88, 152, 139, 105, 181, 88],
+    },
   ],
   errors: [
     { code: 6000, name: "OrderBookFull", msg: "OrderBook is full" },
@@ -248,20 +443,54 @@ export const IDL: OrderbookDex = {
     { code: 6002, name: "ErrorValueInvalid", msg: "Enter Valid Value" },
     { code: 6003, name: "InvalidAta", msg: "The Ata is invalid" },
     { code: 6004, name: "AtaNotFound", msg: "The Ata not found" },
-  ],
-  types: [
     {
-      name: "LimitOrder",
-      type: {
-        kind: "struct",
-        fields: [
-          { name: "owner", type: "pubkey" },
-          { name: "price", type: "u64" },
-          { name: "amount", type: "u64" },
-          { name: "order_id", type: "u64" },
-        ],
-      },
+      code: 6005,
+      name: "InsufficientLiquidity",
+      msg: "Not enough liquidity to fill the whole order",
     },
+    {
+      code: 6006,
+      name: "PostOnlyWouldCross",
+      msg: "Post-only order would cross the spread",
+    },
+    { code: 6007, name: "OrderNotFound", msg: "Order not found" },
+    {
+      code: 6008,
+      name: "DuplicateClientOrderId",
+      msg: "Client order id is already used by a resting order",
+    },
+    {
+      code: 6009,
+      name: "BatchOrderWouldCross",
+      msg: "Batch order would cross the spread",
+    },
+    {
+      code: 6010,
+      name: "TriggerOrdersFull",
+      msg: "Trigger order store is full",
+    },
+    {
+      code: 6011,
+      name: "OrderAlreadyLinked",
+      msg: "Order is already part of a one-cancels-other pair",
+    },
+    {
+      code: 6012,
+      name: "PeggedOrderWouldCross",
+      msg: "Pegged order would cross the spread",
+    },
+    {
+      code: 6013,
+      name: "SlippageExceeded",
+      msg: "Swap output is below the minimum",
+    },
+    {
+      code: 6014,
+      name: "SideAccountsMissing",
+      msg: "The book side or vault of a side the instruction touches was not passed",
+    },
+  ],
+  types: [
     {
       name: "Market",
       type: {
@@ -273,26 +502,93 @@ export const IDL: OrderbookDex = {
           { name: "quote_vault", type: "pubkey" },
           { name: "fee_bps", type: "u16" },
           { name: "creator", type: "pubkey" },
+          {
+            name: "matching",
+            type: { defined: { name: "MatchingAlgorithm" } },
+          },
+        ],
+      },
+    },
+    {
+      name: "MatchingAlgorithm",
+      type: {
+        kind: "enum",
+        variants: [
+          { name: "Fifo" },
+          {
+            name: "ProRata",
+            fields: [{ name: "top_order_priority", type: "bool" }],
+          },
         ],
       },
     },
     {
-      name: "OrderBook",
+      name: "OrderBookSide",
+      serialization: "bytemuck",
+      repr: { kind: "c", packed: true },
       type: {
         kind: "struct",
         fields: [
           { name: "market", type: "pubkey" },
           { name: "next_order_id", type: "u64" },
-          { name: "bids", type: { vec: { defined: { name: "LimitOrder" } } } },
-          { name: "asks", type: { vec: { defined: { name: "LimitOrder" } } } },
+          { name: "capacity", type: "u32" },
+          { name: "len", type: "u32" },
+          { name: "pegged", type: "u32" },
+          { name: "roots", type: { array: ["u32", 4] } },
+          { name: "next_sequence", type: "u64" },
+          { name: "priced_from_bid", type: "u64" },
+          { name: "priced_from_ask", type: "u64" },
+        ],
+      },
+    },
+    {
+      name: "Peg",
+      type: {
+        kind: "enum",
+        variants: [
+          { name: "Fixed" },
+          { name: "BestBid" },
+          { name: "BestAsk" },
+          { name: "Mid" },
+        ],
+      },
+    },
+    {
+      name: "PostOnly",
+      type: {
+        kind: "enum",
+        variants: [
+          { name: "Disabled" },
+          { name: "Reject" },
+          { name: "Slide" },
+        ],
+      },
+    },
+    {
+      name: "SelfTradeBehavior",
+      type: {
+        kind: "enum",
+        variants: [
+          { name: "CancelTaker" },
+          { name: "CancelMaker" },
+          { name: "CancelBoth" },
+          { name: "DecrementAndCancel" },
         ],
       },
     },
     {
       name: "Side",
+      type: { kind: "enum", variants: [{ name: "Bid" }, { name: "Ask" }] },
+    },
+    {
+      name: "TimeInForce",
       type: {
         kind: "enum",
-        variants: [{ name: "Bid" }, { name: "Ask" }],
+        variants: [
+          { name: "GoodTillCancel" },
+          { name: "ImmediateOrCancel" },
+          { name: "FillOrKill" },
+        ],
       },
     },
   ],
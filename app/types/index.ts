@@ -16,11 +16,11 @@ export interface Market {
   creator: PublicKey;
 }
 
-export interface OrderBook {
+// One side of the book; bids and asks are separate accounts
+export interface OrderBookSide {
   market: PublicKey;
-  nextOrderId: bigint;
-  bids: LimitOrder[];
-  asks: LimitOrder[];
+  side: Side;
+  orders: LimitOrder[];
 }
 
 export type Side = "bid" | "ask";
@@ -0,0 +1,763 @@
+use std::cell::RefMut;
+use std::cmp::max;
+use std::mem::size_of;
+
+use anchor_lang::prelude::*;
+
+use crate::states::{LimitOrder, OrderBookSide, Peg};
+
+/// Set on a node handle that points at a leaf rather than an inner node.
+const LEAF: u32 = 1 << 31;
+
+/// Arrival bits of a price key that the fixed-first key keeps, see `BookSide::insert`.
+const SEQUENCE_MASK: u128 = (1 << 63) - 1;
+
+/// Bits of an arrival below the slot, room for that many inserts on a side per slot.
+const SLOT_SHIFT: u32 = 24;
+
+/// Slot in which the order under a price key took its place, see `BookSide::insert`.
+pub fn arrival_slot(key: u128) -> u64 {
+    (key as u64) >> SLOT_SHIFT
+}
+
+/// A resting order with its place in price order.
+#[zero_copy]
+#[repr(C, packed)]
+pub struct Leaf {
+    /// Price priority in the high 64 bits, lowest first, and arrival in the low 64 bits.
+    pub key: u128,
+    pub order: LimitOrder,
+}
+
+/// Branch of a critbit tree.
+#[zero_copy]
+#[repr(C, packed)]
+pub struct InnerNode {
+    /// Highest bit in which the keys below this node differ.
+    pub crit_bit: u8,
+    /// Subtrees with `crit_bit` clear and set, as node handles.
+    pub children: [u32; 2],
+}
+
+/// The critbit trees kept over the leaves of a side. Each holds every leaf.
+#[derive(Clone, Copy)]
+enum Index {
+    /// By `Leaf::key`, for matching in price-time order.
+    Price,
+    /// By order id, for cancels and amends.
+    Id,
+    /// By owner, then client order id, see `owner_key`.
+    Owner,
+    /// Fixed-price orders in price-time order, then the pegged ones, see `fixed_key`.
+    Fixed,
+}
+
+impl Index {
+    const ALL: [Index; INDEXES] = [Index::Price, Index::Id, Index::Owner, Index::Fixed];
+}
+
+pub const INDEXES: usize = 4;
+
+/// Room for one order: its leaf and an inner node of each tree. A side of `n` orders uses the
+/// leaves of its first `n` slots and the inner nodes of its first `n - 1`.
+#[zero_copy]
+#[repr(C, packed)]
+pub struct Slot {
+    pub leaf: Leaf,
+    /// One per `Index`, in its order.
+    pub nodes: [InnerNode; INDEXES],
+}
+
+/// Resting orders of an `OrderBookSide` account, in a slab of slots indexed by critbit trees:
+/// by price-time key, by order id, by owner and client order id, and fixed-price orders first.
+pub struct BookSide<'a> {
+    pub header: RefMut<'a, OrderBookSide>,
+    pub slots: RefMut<'a, [Slot]>,
+    /// Lowest arrival an order inserted now can get.
+    first_arrival: u64,
+}
+
+impl<'a> BookSide<'a> {
+    /// Borrows the data of `loader` for as long as the side is held. Checks the discriminator
+    /// like `AccountLoader::load_mut`.
+    pub fn load(loader: &'a AccountLoader<'_, OrderBookSide>) -> Result<Self> {
+        let data = RefMut::map(loader.as_ref().try_borrow_mut_data()?, |data| &mut data[..]);
+        require!(
+            data.len() >= 8 && data[..8] == *OrderBookSide::DISCRIMINATOR,
+            ErrorCode::AccountDiscriminatorMismatch
+        );
+
+        let mut side = Self::from_data(RefMut::map(data, |data| &mut data[8..]));
+        side.first_arrival = Clock::get()?.slot << SLOT_SHIFT;
+        Ok(side)
+    }
+
+    /// A side over `data`, the account data after the discriminator.
+    fn from_data(data: RefMut<'a, [u8]>) -> Self {
+        let (header, slots) =
+            RefMut::map_split(data, |data| data.split_at_mut(size_of::<OrderBookSide>()));
+        let header: RefMut<OrderBookSide> =
+            RefMut::map(header, |header| bytemuck::from_bytes_mut(header));
+        let slots_space = header.capacity as usize * size_of::<Slot>();
+        BookSide {
+            header,
+            slots: RefMut::map(slots, |slots| {
+                bytemuck::cast_slice_mut(&mut slots[..slots_space])
+            }),
+            first_arrival: 0,
+        }
+    }
+
+    /// Orders the side has room for.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.len == 0
+    }
+
+    /// The order first in price-time order.
+    pub fn best(&self) -> Option<&LimitOrder> {
+        let leaf = self.min_leaf(Index::Price, self.root(Index::Price)?);
+        Some(&self.slots[leaf as usize].leaf.order)
+    }
+
+    /// The order first in price-time order. Only its amounts and links may change.
+    pub fn best_mut(&mut self) -> Option<&mut LimitOrder> {
+        let leaf = self.min_leaf(Index::Price, self.root(Index::Price)?);
+        Some(&mut self.slots[leaf as usize].leaf.order)
+    }
+
+    /// The fixed-price order first in price-time order, what pegged orders follow.
+    pub fn best_fixed(&self) -> Option<&LimitOrder> {
+        let leaf = self.min_leaf(Index::Fixed, self.root(Index::Fixed)?);
+        let order = &self.slots[leaf as usize].leaf.order;
+        (order.peg() == Peg::Fixed).then_some(order)
+    }
+
+    /// Orders in price-time order.
+    pub fn iter(&self) -> Iter<'_> {
+        self.iter_by(Index::Price)
+    }
+
+    fn iter_by(&self, index: Index) -> Iter<'_> {
+        Iter {
+            slots: &self.slots,
+            index,
+            stack: self.root(index).into_iter().collect(),
+        }
+    }
+
+    /// The order holding client order id `client_order_id` of `owner`. Owners are told
+    /// apart by the first 8 bytes of their key only, so this can be the order of another
+    /// owner whose key starts the same way; either way no other order can take that id.
+    pub fn by_client_id(&self, owner: &Pubkey, client_order_id: u64) -> Option<&LimitOrder> {
+        if client_order_id == 0 || client_order_id > LimitOrder::MAX_CLIENT_ORDER_ID {
+            return None;
+        }
+        let leaf = self.find(Index::Owner, owner_prefix(owner) | client_order_id as u128)?;
+        Some(&self.slots[leaf as usize].leaf.order)
+    }
+
+    /// Ids of `owner`'s orders.
+    pub fn owned_by(&self, owner: &Pubkey) -> Vec<u64> {
+        let prefix = owner_prefix(owner);
+        let mut order_ids = Vec::new();
+        let mut key = prefix;
+        while let Some(leaf) = self.leaf_from(Index::Owner, key) {
+            key = self.key_of(Index::Owner, leaf);
+            if key >> 64 != prefix >> 64 {
+                break;
+            }
+            let order = &self.slots[leaf as usize].leaf.order;
+            if order.owner == *owner {
+                order_ids.push(order.order_id);
+            }
+            key += 1;
+        }
+        order_ids
+    }
+
+    pub fn get(&self, order_id: u64) -> Option<&LimitOrder> {
+        let leaf = self.find(Index::Id, order_id as u128)?;
+        Some(&self.slots[leaf as usize].leaf.order)
+    }
+
+    /// Order `order_id`. Only its amounts and links may change, its place in the book stays.
+    pub fn get_mut(&mut self, order_id: u64) -> Option<&mut LimitOrder> {
+        let leaf = self.find(Index::Id, order_id as u128)?;
+        Some(&mut self.slots[leaf as usize].leaf.order)
+    }
+
+    /// First order at or after `key` in price-time order, with its key.
+    pub fn first_from(&self, key: u128) -> Option<(u128, LimitOrder)> {
+        let leaf = self.leaf_from(Index::Price, key)?;
+        Some((
+            self.key_of(Index::Price, leaf),
+            self.slots[leaf as usize].leaf.order,
+        ))
+    }
+
+    /// Leaf with the lowest key at or after `key` in the tree of `index`.
+    fn leaf_from(&self, index: Index, key: u128) -> Option<u32> {
+        let root = self.root(index)?;
+        let closest = self.closest(index, root, key);
+        let closest_key = self.key_of(index, closest);
+        if closest_key == key {
+            return Some(closest);
+        }
+
+        // Everything under the node where `key` would branch off sorts the same way against
+        // it; when that is below `key` the answer is the next subtree to the right
+        let crit_bit = crit_bit(key, closest_key);
+        let mut node = root;
+        let mut next_right = None;
+        while node & LEAF == 0 {
+            let inner = self.node(index, node);
+            if inner.crit_bit < crit_bit {
+                break;
+            }
+            let children = inner.children;
+            let branch = bit(key, inner.crit_bit);
+            if branch == 0 {
+                next_right = Some(children[1]);
+            }
+            node = children[branch];
+        }
+
+        let subtree = match bit(key, crit_bit) {
+            0 => node,
+            _ => next_right?,
+        };
+        Some(self.min_leaf(index, subtree))
+    }
+
+    /// Adds `order` behind every order of the same or better `priority`, lower being better.
+    /// Callers check there is a free slot.
+    ///
+    /// The low half of the key is the order's arrival: the slot it was inserted in, then a
+    /// count of the inserts on this side in that slot. Arrivals only grow on a side, and
+    /// those of bids and asks compare by slot even though each side counts on its own.
+    pub fn insert(&mut self, priority: u64, order: LimitOrder) {
+        let leaf = self.header.len;
+        assert!(!self.is_full(), "book side is full");
+
+        let sequence = max(self.header.next_sequence, self.first_arrival);
+        self.header.next_sequence = sequence + 1;
+        self.slots[leaf as usize].leaf = Leaf {
+            key: (priority as u128) << 64 | sequence as u128,
+            order,
+        };
+        for index in Index::ALL {
+            self.link(index, leaf);
+        }
+
+        self.header.len += 1;
+        if order.peg() != Peg::Fixed {
+            self.header.pegged += 1;
+        }
+    }
+
+    /// Takes order `order_id` out.
+    pub fn remove(&mut self, order_id: u64) -> Option<LimitOrder> {
+        let leaf = self.find(Index::Id, order_id as u128)?;
+        let order = self.slots[leaf as usize].leaf.order;
+        for index in Index::ALL {
+            let key = self.key_of(index, leaf);
+            self.unlink(index, key);
+        }
+
+        let last = self.header.len - 1;
+        if leaf != last {
+            self.slots[leaf as usize].leaf = self.slots[last as usize].leaf;
+            for index in Index::ALL {
+                let key = self.key_of(index, leaf);
+                let parent = self.parent_of(index, LEAF | last, key);
+                self.set_child(index, parent, LEAF | leaf);
+            }
+        }
+
+        self.header.len -= 1;
+        if order.peg() != Peg::Fixed {
+            self.header.pegged -= 1;
+        }
+        Some(order)
+    }
+
+    /// Takes every order matching `predicate` out, in price-time order.
+    pub fn drain_where(&mut self, predicate: impl Fn(&LimitOrder) -> bool) -> Vec<LimitOrder> {
+        let ids: Vec<u64> = self
+            .iter()
+            .filter(|order| predicate(order))
+            .map(|order| order.order_id)
+            .collect();
+        ids.into_iter()
+            .filter_map(|order_id| self.remove(order_id))
+            .collect()
+    }
+
+    fn root(&self, index: Index) -> Option<u32> {
+        if self.header.len == 0 {
+            return None;
+        }
+        let roots = self.header.roots;
+        Some(roots[index as usize])
+    }
+
+    fn node(&self, index: Index, node: u32) -> InnerNode {
+        self.slots[node as usize].nodes[index as usize]
+    }
+
+    fn set_node(&mut self, index: Index, node: u32, inner: InnerNode) {
+        self.slots[node as usize].nodes[index as usize] = inner;
+    }
+
+    fn key_of(&self, index: Index, leaf: u32) -> u128 {
+        let leaf = self.slots[leaf as usize].leaf;
+        match index {
+            Index::Price => leaf.key,
+            Index::Id => leaf.order.order_id as u128,
+            Index::Owner => owner_key(&leaf.order),
+            Index::Fixed => fixed_key(&leaf),
+        }
+    }
+
+    /// Points `parent`'s branch, or the root when there is no parent, at `handle`.
+    fn set_child(&mut self, index: Index, parent: Option<(u32, usize)>, handle: u32) {
+        match parent {
+            None => {
+                let mut roots = self.header.roots;
+                roots[index as usize] = handle;
+                self.header.roots = roots;
+            }
+            Some((node, branch)) => {
+                let mut inner = self.node(index, node);
+                let mut children = inner.children;
+                children[branch] = handle;
+                inner.children = children;
+                self.set_node(index, node, inner);
+            }
+        }
+    }
+
+    /// Leaf reached by following `key` down from `node`. Shares the longest prefix with
+    /// `key` of all leaves below `node`.
+    fn closest(&self, index: Index, mut node: u32, key: u128) -> u32 {
+        while node & LEAF == 0 {
+            let inner = self.node(index, node);
+            node = inner.children[bit(key, inner.crit_bit)];
+        }
+        node & !LEAF
+    }
+
+    fn min_leaf(&self, index: Index, mut node: u32) -> u32 {
+        while node & LEAF == 0 {
+            node = self.node(index, node).children[0];
+        }
+        node & !LEAF
+    }
+
+    fn find(&self, index: Index, key: u128) -> Option<u32> {
+        let leaf = self.closest(index, self.root(index)?, key);
+        (self.key_of(index, leaf) == key).then_some(leaf)
+    }
+
+    /// Inner node and branch pointing at `handle`, found by following `key` of a leaf below it.
+    fn parent_of(&self, index: Index, handle: u32, key: u128) -> Option<(u32, usize)> {
+        let mut node = self.root(index)?;
+        let mut parent = None;
+        while node != handle {
+            let inner = self.node(index, node);
+            let branch = bit(key, inner.crit_bit);
+            parent = Some((node, branch));
+            node = inner.children[branch];
+        }
+        parent
+    }
+
+    /// Adds `leaf` to the tree of `index`, with the next free inner node above it.
+    fn link(&mut self, index: Index, leaf: u32) {
+        let key = self.key_of(index, leaf);
+        let Some(root) = self.root(index) else {
+            self.set_child(index, None, LEAF | leaf);
+            return;
+        };
+
+        let closest = self.closest(index, root, key);
+        let crit_bit = crit_bit(key, self.key_of(index, closest));
+
+        // Crit bits fall going down, the new node goes above the first one lower than its own
+        let mut node = root;
+        let mut parent = None;
+        while node & LEAF == 0 {
+            let inner = self.node(index, node);
+            if inner.crit_bit < crit_bit {
+                break;
+            }
+            let branch = bit(key, inner.crit_bit);
+            parent = Some((node, branch));
+            node = inner.children[branch];
+        }
+
+        let mut children = [node; 2];
+        children[bit(key, crit_bit)] = LEAF | leaf;
+        let new_node = self.header.len - 1;
+        self.set_node(index, new_node, InnerNode { crit_bit, children });
+        self.set_child(index, parent, new_node);
+    }
+
+    /// Takes the leaf with `key` out of the tree of `index`. Its parent node is freed and the
+    /// last inner node in use moves into its slot.
+    fn unlink(&mut self, index: Index, key: u128) {
+        let Some(mut node) = self.root(index) else {
+            return;
+        };
+        let mut grandparent = None;
+        let mut parent = None;
+        while node & LEAF == 0 {
+            let inner = self.node(index, node);
+            let branch = bit(key, inner.crit_bit);
+            grandparent = parent;
+            parent = Some((node, branch));
+            node = inner.children[branch];
+        }
+
+        let Some((freed, branch)) = parent else {
+            return;
+        };
+        let sibling = self.node(index, freed).children[1 - branch];
+        self.set_child(index, grandparent, sibling);
+
+        let last = self.header.len - 2;
+        if freed != last {
+            let moved = self.node(index, last);
+            self.set_node(index, freed, moved);
+            let key = self.key_of(index, self.min_leaf(index, freed));
+            let parent = self.parent_of(index, last, key);
+            self.set_child(index, parent, freed);
+        }
+    }
+}
+
+/// In-order walk over a tree of a `BookSide`.
+pub struct Iter<'a> {
+    slots: &'a [Slot],
+    index: Index,
+    stack: Vec<u32>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a LimitOrder;
+
+    fn next(&mut self) -> Option<&'a LimitOrder> {
+        while let Some(node) = self.stack.pop() {
+            if node & LEAF != 0 {
+                return Some(&self.slots[(node & !LEAF) as usize].leaf.order);
+            }
+            let children = self.slots[node as usize].nodes[self.index as usize].children;
+            self.stack.push(children[1]);
+            self.stack.push(children[0]);
+        }
+        None
+    }
+}
+
+/// `owner`'s first 8 bytes in the high half of a key, what the owner tree sorts by first.
+fn owner_prefix(owner: &Pubkey) -> u128 {
+    let head: [u8; 8] = owner.as_ref()[..8].try_into().unwrap();
+    (u64::from_le_bytes(head) as u128) << 64
+}
+
+/// Key of `order` in the owner tree: its owner's prefix, then its client order id, or for an
+/// order without one its order id above every client order id. Placing checks no order holds
+/// the key yet, so keys stay unique even for owners that share a prefix.
+fn owner_key(order: &LimitOrder) -> u128 {
+    let low = match order.client_order_id {
+        0 => 1 << 63 | order.order_id,
+        client_order_id => client_order_id,
+    };
+    owner_prefix(&order.owner) | low as u128
+}
+
+/// Key of `leaf` in the fixed-first tree: its price-time key behind a top bit set for pegged
+/// orders, so the lowest key is the best fixed-price order when there is one.
+fn fixed_key(leaf: &Leaf) -> u128 {
+    let pegged = (leaf.order.peg() != Peg::Fixed) as u128;
+    pegged << 127 | leaf.key >> 64 << 63 | leaf.key & SEQUENCE_MASK
+}
+
+fn bit(key: u128, crit_bit: u8) -> usize {
+    (key >> crit_bit & 1) as usize
+}
+
+/// Highest bit in which two different keys differ.
+fn crit_bit(a: u128, b: u128) -> u8 {
+    (127 - (a ^ b).leading_zeros()) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    const CAPACITY: u32 = 24;
+
+    /// xorshift64*, enough to shuffle operations without a dependency.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32) % n
+        }
+    }
+
+    fn account(capacity: u32) -> RefCell<Vec<u8>> {
+        let mut data = vec![0; OrderBookSide::space(capacity)];
+        let header: &mut OrderBookSide =
+            bytemuck::from_bytes_mut(&mut data[..size_of::<OrderBookSide>()]);
+        header.capacity = capacity;
+        RefCell::new(data)
+    }
+
+    fn side(account: &RefCell<Vec<u8>>) -> BookSide<'_> {
+        BookSide::from_data(RefMut::map(account.borrow_mut(), |data| &mut data[..]))
+    }
+
+    fn owner(n: u8) -> Pubkey {
+        Pubkey::new_from_array([n; 32])
+    }
+
+    fn order(owner: Pubkey, order_id: u64, client_order_id: u64, peg: Peg) -> LimitOrder {
+        LimitOrder {
+            owner,
+            price: 10,
+            amount: 1,
+            order_id,
+            client_order_id,
+            expires_at: 0,
+            self_trade_behavior: 0,
+            display_amount: 0,
+            hidden_amount: 0,
+            linked_order_id: 0,
+            peg: peg as u8,
+            peg_offset: 0,
+        }
+    }
+
+    /// Checks every way of reading `side` against `model`, the orders by price-time key.
+    fn assert_matches(side: &BookSide, model: &BTreeMap<u128, LimitOrder>, rng: &mut Rng) {
+        let ids = |orders: Vec<&LimitOrder>| -> Vec<u64> {
+            orders.iter().map(|order| order.order_id).collect()
+        };
+
+        assert_eq!(side.len(), model.len());
+        assert_eq!(ids(side.iter().collect()), ids(model.values().collect()));
+        assert_eq!(
+            side.best().map(|order| order.order_id),
+            model.values().next().map(|order| order.order_id)
+        );
+        assert_eq!(
+            side.best_fixed().map(|order| order.order_id),
+            model
+                .values()
+                .find(|order| order.peg() == Peg::Fixed)
+                .map(|order| order.order_id)
+        );
+
+        // Each tree holds every order, in strictly increasing key order
+        for index in Index::ALL {
+            let keys: Vec<u128> = side
+                .iter_by(index)
+                .map(|order| {
+                    let leaf = side.find(Index::Id, order.order_id as u128).unwrap();
+                    side.key_of(index, leaf)
+                })
+                .collect();
+            assert_eq!(keys.len(), model.len());
+            assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+
+        for (&key, expected) in model {
+            let order_id = expected.order_id;
+            assert_eq!(
+                side.get(order_id).map(|order| order.order_id),
+                Some(order_id)
+            );
+            assert_eq!(side.first_from(key).map(|(found, _)| found), Some(key));
+            if expected.client_order_id != 0 {
+                let found = side.by_client_id(&expected.owner, expected.client_order_id);
+                assert_eq!(found.map(|order| order.order_id), Some(order_id));
+            }
+        }
+
+        for _ in 0..8 {
+            let probe = (rng.below(10) as u128) << 64 | rng.below(400) as u128;
+            assert_eq!(
+                side.first_from(probe)
+                    .map(|(key, order)| (key, order.order_id)),
+                model
+                    .range(probe..)
+                    .next()
+                    .map(|(&key, order)| (key, order.order_id))
+            );
+        }
+
+        for n in 0..3 {
+            let mut owned = side.owned_by(&owner(n));
+            owned.sort();
+            let mut expected: Vec<u64> = model
+                .values()
+                .filter(|order| order.owner == owner(n))
+                .map(|order| order.order_id)
+                .collect();
+            expected.sort();
+            assert_eq!(owned, expected);
+        }
+    }
+
+    #[test]
+    fn matches_a_sorted_map_through_inserts_and_removes() {
+        let account = account(CAPACITY);
+        let mut side = side(&account);
+        let mut model = BTreeMap::new();
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut next_order_id = 1;
+
+        for _ in 0..2000 {
+            let insert = model.is_empty() || (!side.is_full() && rng.below(5) < 3);
+            if insert {
+                let owner = owner(rng.below(3) as u8);
+                // Unique client order ids keep the owner tree keys unique, as placing checks
+                let client_order_id = match rng.below(2) {
+                    0 => 0,
+                    _ => next_order_id * 7,
+                };
+                let peg = match rng.below(4) {
+                    0 => Peg::BestBid,
+                    _ => Peg::Fixed,
+                };
+                let new_order = order(owner, next_order_id, client_order_id, peg);
+                next_order_id += 1;
+
+                // Few prices, so many orders share one and fall back to arrival
+                let priority = rng.below(10);
+                let key = (priority as u128) << 64 | side.header.next_sequence as u128;
+                side.insert(priority, new_order);
+                model.insert(key, new_order);
+            } else {
+                let nth = rng.below(model.len() as u64) as usize;
+                let key = *model.keys().nth(nth).unwrap();
+                let expected = model.remove(&key).unwrap();
+                let removed = side.remove(expected.order_id).unwrap();
+                assert_eq!({ removed.order_id }, { expected.order_id });
+                assert!(side.remove(expected.order_id).is_none());
+            }
+
+            assert_matches(&side, &model, &mut rng);
+        }
+
+        let pegged = model
+            .values()
+            .filter(|order| order.peg() != Peg::Fixed)
+            .count();
+        assert_eq!(side.header.pegged as usize, pegged);
+    }
+
+    #[test]
+    fn drains_to_empty_and_fills_again() {
+        let account = account(CAPACITY);
+        let mut side = side(&account);
+
+        for round in 0..3u64 {
+            for n in 0..CAPACITY as u64 {
+                let order_id = round * 100 + n + 1;
+                side.insert(n % 4, order(owner(0), order_id, 0, Peg::Fixed));
+            }
+            assert!(side.is_full());
+
+            let drained = side.drain_where(|order| order.order_id % 2 == 0);
+            assert!(drained.iter().all(|order| order.order_id % 2 == 0));
+            assert_eq!(side.len(), CAPACITY as usize - drained.len());
+            assert_eq!(side.owned_by(&owner(0)).len(), side.len());
+
+            for order_id in side.owned_by(&owner(0)) {
+                side.remove(order_id).unwrap();
+            }
+            assert!(side.is_empty());
+            assert!(side.best().is_none());
+            assert!(side.first_from(0).is_none());
+        }
+    }
+
+    #[test]
+    fn arrivals_start_at_the_slot_and_never_go_back() {
+        let account = account(CAPACITY);
+        let mut side = side(&account);
+        let arrival = |side: &BookSide, order_id: u64| {
+            let leaf = side.find(Index::Id, order_id as u128).unwrap();
+            side.key_of(Index::Price, leaf) as u64
+        };
+
+        side.first_arrival = 7 << SLOT_SHIFT;
+        side.insert(0, order(owner(0), 1, 0, Peg::Fixed));
+        side.insert(0, order(owner(0), 2, 0, Peg::Fixed));
+        assert_eq!(arrival(&side, 1), 7 << SLOT_SHIFT);
+        assert_eq!(arrival(&side, 2), (7 << SLOT_SHIFT) + 1);
+
+        // Loaded again in the same slot, the side keeps counting from its last insert
+        side.first_arrival = 7 << SLOT_SHIFT;
+        side.insert(0, order(owner(0), 3, 0, Peg::Fixed));
+        assert_eq!(arrival(&side, 3), (7 << SLOT_SHIFT) + 2);
+
+        side.first_arrival = 9 << SLOT_SHIFT;
+        side.insert(0, order(owner(0), 4, 0, Peg::Fixed));
+        assert_eq!(arrival(&side, 4), 9 << SLOT_SHIFT);
+        assert_eq!(
+            side.iter().map(|order| order.order_id).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn owners_sharing_a_key_prefix_share_client_order_ids() {
+        let account = account(CAPACITY);
+        let mut side = side(&account);
+        let first = owner(1);
+        let mut bytes = [1; 32];
+        bytes[31] = 2;
+        let second = Pubkey::new_from_array(bytes);
+
+        side.insert(0, order(first, 1, 5, Peg::Fixed));
+        side.insert(0, order(second, 2, 6, Peg::Fixed));
+
+        // The id is taken either way, whose order holds it is for the caller to check
+        assert_eq!(
+            side.by_client_id(&second, 5).map(|order| order.order_id),
+            Some(1)
+        );
+        assert_eq!(
+            side.by_client_id(&first, 6).map(|order| order.order_id),
+            Some(2)
+        );
+        assert!(side.by_client_id(&first, 7).is_none());
+        assert!(side
+            .by_client_id(&first, LimitOrder::MAX_CLIENT_ORDER_ID + 1)
+            .is_none());
+        assert_eq!(side.owned_by(&first), vec![1]);
+        assert_eq!(side.owned_by(&second), vec![2]);
+    }
+}
@@ -12,4 +12,24 @@ pub enum OrderBookError {
     InvalidAta,
     #[msg("The Ata not found")]
     AtaNotFound,
+    #[msg("Not enough liquidity to fill the whole order")]
+    InsufficientLiquidity,
+    #[msg("Post-only order would cross the spread")]
+    PostOnlyWouldCross,
+    #[msg("Order not found")]
+    OrderNotFound,
+    #[msg("Client order id is already used by a resting order")]
+    DuplicateClientOrderId,
+    #[msg("Batch order would cross the spread")]
+    BatchOrderWouldCross,
+    #[msg("Trigger order store is full")]
+    TriggerOrdersFull,
+    #[msg("Order is already part of a one-cancels-other pair")]
+    OrderAlreadyLinked,
+    #[msg("Pegged order would cross the spread")]
+    PeggedOrderWouldCross,
+    #[msg("Swap output is below the minimum")]
+    SlippageExceeded,
+    #[msg("The book side or vault of a side the instruction touches was not passed")]
+    SideAccountsMissing,
 }
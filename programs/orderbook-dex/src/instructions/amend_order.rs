@@ -0,0 +1,115 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::{
+    errors::OrderBookError,
+    settlement::{escrow_of, Vault},
+    states::{Market, OrderBookSide, SideBook},
+    Side,
+};
+
+#[derive(Accounts)]
+#[instruction(side: Side)]
+pub struct AmendOrder<'info> {
+    #[account(mut)]
+    pub trader: Signer<'info>,
+
+    #[account(
+        seeds=[b"market", base_mint.key().as_ref(), quote_mint.key().as_ref()],
+        bump
+    )]
+    pub market: Account<'info, Market>,
+
+    pub base_mint: InterfaceAccount<'info, Mint>,
+
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    /// The side the order rests on.
+    #[account(mut, seeds = [side.book_seed(), market.key().as_ref()], bump)]
+    pub book_side: AccountLoader<'info, OrderBookSide>,
+
+    /// Quote vault for bids, base vault for asks.
+    #[account(
+        mut,
+        address = match side {
+            Side::Bid => market.quote_vault,
+            Side::Ask => market.base_vault,
+        }
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// The trader's account of the vault's mint.
+    #[account(mut)]
+    pub trader_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> AmendOrder<'info> {
+    /// Changes the price and/or size of a resting order in place, keeping its `order_id`.
+    /// Only the difference in escrow is moved: topped up from the trader or refunded. Like
+    /// `post_order` it only sees the order's own side, so a new price that crosses is left
+    /// for the `match_order` crank.
+    pub fn amend_order(
+        &mut self,
+        side: Side,
+        order_id: u64,
+        new_price: u64,
+        new_amount: u64,
+        bump: u8,
+    ) -> Result<()> {
+        require!(
+            new_price > 0 && new_amount > 0,
+            OrderBookError::ErrorValueInvalid
+        );
+
+        let (previous, amended) = SideBook::load(side.clone(), &self.book_side)?
+            .amend_order(&self.trader.key(), order_id, new_price, new_amount)
+            .ok_or(OrderBookError::OrderNotFound)?;
+
+        let base_mint_key = self.base_mint.key();
+        let quote_mint_key = self.quote_mint.key();
+        let seeds = &[
+            b"market",
+            base_mint_key.as_ref(),
+            quote_mint_key.as_ref(),
+            &[bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let mint = match side {
+            Side::Bid => &self.quote_mint,
+            Side::Ask => &self.base_mint,
+        };
+        let vault = Vault {
+            market: &self.market,
+            mint,
+            vault: &self.vault,
+            token_program: &self.token_program,
+            signer_seeds,
+        };
+
+        let old_escrow = escrow_of(&side, &previous, self.base_mint.decimals)?;
+        let new_escrow = escrow_of(&side, &amended, self.base_mint.decimals)?;
+
+        if new_escrow > old_escrow {
+            let ctx_acc = TransferChecked {
+                from: self.trader_token_account.to_account_info(),
+                to: self.vault.to_account_info(),
+                mint: mint.to_account_info(),
+                authority: self.trader.to_account_info(),
+            };
+
+            let ctx = CpiContext::new(self.token_program.to_account_info(), ctx_acc);
+
+            token_interface::transfer_checked(ctx, new_escrow - old_escrow, mint.decimals)?;
+        } else if old_escrow > new_escrow {
+            vault.pay(
+                self.trader_token_account.to_account_info(),
+                old_escrow - new_escrow,
+            )?;
+        }
+
+        Ok(())
+    }
+}
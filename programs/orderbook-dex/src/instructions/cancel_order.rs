@@ -1,120 +1,204 @@
 use anchor_lang::prelude::*;
-use anchor_spl::{
-    associated_token::AssociatedToken,
-    token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked},
-};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
 use crate::{
-    states::{Market, OrderBook},
+    errors::OrderBookError,
+    settlement::{escrow_of, Vault},
+    states::{Market, OrderBookSide, SideBook},
     LimitOrder, Side,
 };
 
+/// The accounts of a side are only needed when a cancel touches that side.
 #[derive(Accounts)]
 pub struct CancelOrder<'info> {
     ///CHECK: this is the trader whose order is being cancel
     #[account(mut)]
     pub trader: Signer<'info>,
 
-    #[account(mut, 
-        seeds=[b"market", base_mint.key().as_ref(), quote_mint.key().as_ref()], 
+    #[account(
+        seeds=[b"market", base_mint.key().as_ref(), quote_mint.key().as_ref()],
         bump
-)]
+    )]
     pub market: Account<'info, Market>,
 
-    #[account(mut)]
     pub base_mint: InterfaceAccount<'info, Mint>,
 
-    #[account(mut)]
     pub quote_mint: InterfaceAccount<'info, Mint>,
 
-    #[account(mut, seeds=[b"market_orderBook", market.key().as_ref()], bump)]
-    pub order_book: Account<'info, OrderBook>,
+    #[account(mut, seeds = [b"market_bids", market.key().as_ref()], bump)]
+    pub bids: Option<AccountLoader<'info, OrderBookSide>>,
 
-    #[account(mut)]
-    pub base_mint_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, seeds = [b"market_asks", market.key().as_ref()], bump)]
+    pub asks: Option<AccountLoader<'info, OrderBookSide>>,
 
-    #[account(mut)]
-    pub quote_mint_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = market.base_vault)]
+    pub base_mint_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut, address = market.quote_vault)]
+    pub quote_mint_vault: Option<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(mut)]
-    pub trader_base_mint_account: InterfaceAccount<'info, TokenAccount>,
+    pub trader_base_mint_account: Option<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(mut)]
-    pub trader_quote_mint_account: InterfaceAccount<'info, TokenAccount>,
+    pub trader_quote_mint_account: Option<InterfaceAccount<'info, TokenAccount>>,
 
     pub token_program: Interface<'info, TokenInterface>,
 }
 
 impl<'info> CancelOrder<'info> {
+    /// Cancels the trader's order `order_id` on `side`, and the other leg of its
+    /// one-cancels-other pair, whose side then has to be passed too.
     pub fn cancel_order(&mut self, side: Side, order_id: u64, bump: u8) -> Result<()> {
-        let market = self.market.key();
+        let mut order_book = self.side_book(&side)?;
+        let order = order_book
+            .remove_order(&self.trader.key(), order_id)
+            .ok_or(OrderBookError::OrderNotFound)?;
+
+        let mut cancelled = vec![(side, order)];
+        cancelled.extend(self.remove_linked(&mut order_book, &order)?);
+        drop(order_book);
+
+        self.refund(&cancelled, bump)
+    }
+
+    /// Cancels the order holding `client_order_id`, looked for on the sides passed.
+    pub fn cancel_order_by_client_id(&mut self, client_order_id: u64, bump: u8) -> Result<()> {
+        require!(client_order_id != 0, OrderBookError::ErrorValueInvalid);
+
+        let trader = self.trader.key();
+        for side in self.passed_sides() {
+            let order_id = self
+                .side_book(&side)?
+                .book
+                .by_client_id(&trader, client_order_id)
+                .map(|order| order.order_id);
+            if let Some(order_id) = order_id {
+                return self.cancel_order(side, order_id, bump);
+            }
+        }
+
+        err!(OrderBookError::OrderNotFound)
+    }
+
+    /// Cancels every order of the trader, on one side only when `side` is given and on the
+    /// sides passed otherwise. The other leg of a one-cancels-other pair goes with it either
+    /// way.
+    pub fn cancel_all_orders(&mut self, side: Option<Side>, bump: u8) -> Result<()> {
+        let trader = self.trader.key();
+        let sides = match side {
+            Some(side) => vec![side],
+            None => self.passed_sides(),
+        };
+
+        let mut cancelled = Vec::new();
+        for side in sides {
+            let mut order_book = self.side_book(&side)?;
+            for order in order_book.remove_owned(&trader) {
+                cancelled.push((side.clone(), order));
+                cancelled.extend(self.remove_linked(&mut order_book, &order)?);
+            }
+        }
+        msg!("Cancelled {} orders", cancelled.len());
+
+        self.refund(&cancelled, bump)
+    }
+
+    fn loader(&self, side: &Side) -> Option<&AccountLoader<'info, OrderBookSide>> {
+        match side {
+            Side::Bid => self.bids.as_ref(),
+            Side::Ask => self.asks.as_ref(),
+        }
+    }
+
+    fn passed_sides(&self) -> Vec<Side> {
+        [Side::Bid, Side::Ask]
+            .into_iter()
+            .filter(|side| self.loader(side).is_some())
+            .collect()
+    }
+
+    fn side_book(&self, side: &Side) -> Result<SideBook<'_>> {
+        let loader = self
+            .loader(side)
+            .ok_or(OrderBookError::SideAccountsMissing)?;
+        SideBook::load(side.clone(), loader)
+    }
+
+    /// Removes the other leg of `order`'s one-cancels-other pair, if it is still in the book.
+    /// `order_book` is the side `order` was on; the leg may rest on either.
+    fn remove_linked(
+        &self,
+        order_book: &mut SideBook,
+        order: &LimitOrder,
+    ) -> Result<Option<(Side, LimitOrder)>> {
+        if order.linked_order_id == 0 {
+            return Ok(None);
+        }
+
+        let side = Side::of_order(order.linked_order_id);
+        let linked = if side == order_book.side {
+            order_book.remove_order(&order.owner, order.linked_order_id)
+        } else {
+            self.side_book(&side)?
+                .remove_order(&order.owner, order.linked_order_id)
+        };
+        Ok(linked.map(|linked| (side, linked)))
+    }
+
+    /// Returns the escrow of cancelled orders to the trader, one transfer per mint.
+    fn refund(&self, orders: &[(Side, LimitOrder)], bump: u8) -> Result<()> {
         let quote_mint = self.quote_mint.key();
         let base_mint = self.base_mint.key();
 
         let seeds = &[b"market", base_mint.as_ref(), quote_mint.as_ref(), &[bump]];
 
         let signer_seeds = &[&seeds[..]];
-        let orderbook = &self.order_book;
 
-        match side {
-            Side::Bid => {
-                let mut quote_amount: u64 = 0;
-
-                for bid in &orderbook.bids {
-                    if bid.order_id == order_id {
-                        quote_amount = bid.amount.checked_mul(bid.price).unwrap();
-                    }
-                }
-
-                self.order_book.bids.retain(|bid: &LimitOrder| {
-                    !(bid.order_id == order_id && bid.owner == self.trader.key())
-                });
-
-                let ctx_acc = TransferChecked {
-                    mint: self.quote_mint.to_account_info(),
-                    from: self.quote_mint_vault.to_account_info(),
-                    to: self.trader_quote_mint_account.to_account_info(),
-                    authority: self.market.to_account_info(),
-                };
-
-                let ctx_refund = CpiContext::new(self.token_program.to_account_info(), ctx_acc)
-                    .with_signer(signer_seeds);
-                token_interface::transfer_checked(
-                    ctx_refund,
-                    quote_amount,
-                    self.quote_mint.decimals,
-                )?;
-            }
+        let mut base_refund: u64 = 0;
+        let mut quote_refund: u64 = 0;
+
+        for (side, order) in orders {
+            let escrow = escrow_of(side, order, self.base_mint.decimals)?;
+            let total = match side {
+                Side::Bid => &mut quote_refund,
+                Side::Ask => &mut base_refund,
+            };
+            *total = total
+                .checked_add(escrow)
+                .ok_or(OrderBookError::ErrorInMultiply)?;
+        }
 
-            Side::Ask => {
-                let mut base_amount: u64 = 0;
-
-                for ask in &orderbook.asks {
-                    if ask.order_id == order_id {
-                        base_amount = ask.amount;
-                    }
-                }
-
-                self.order_book.asks.retain(|bid: &LimitOrder| {
-                    !(bid.order_id == order_id && bid.owner == self.trader.key())
-                });
-
-                let ctx_acc = TransferChecked {
-                    mint: self.base_mint.to_account_info(),
-                    from: self.base_mint_vault.to_account_info(),
-                    to: self.trader_base_mint_account.to_account_info(),
-                    authority: self.market.to_account_info(),
-                };
-
-                let ctx_refund = CpiContext::new(self.token_program.to_account_info(), ctx_acc)
-                    .with_signer(signer_seeds);
-                token_interface::transfer_checked(
-                    ctx_refund,
-                    base_amount,
-                    self.base_mint.decimals,
-                )?;
+        for (side, refund) in [(Side::Bid, quote_refund), (Side::Ask, base_refund)] {
+            if refund == 0 {
+                continue;
             }
+            let (mint, vault, trader_account) = match side {
+                Side::Bid => (
+                    &self.quote_mint,
+                    &self.quote_mint_vault,
+                    &self.trader_quote_mint_account,
+                ),
+                Side::Ask => (
+                    &self.base_mint,
+                    &self.base_mint_vault,
+                    &self.trader_base_mint_account,
+                ),
+            };
+            let (Some(vault), Some(trader_account)) = (vault, trader_account) else {
+                return err!(OrderBookError::SideAccountsMissing);
+            };
+
+            let vault = Vault {
+                market: &self.market,
+                mint,
+                vault,
+                token_program: &self.token_program,
+                signer_seeds,
+            };
+            vault.pay(trader_account.to_account_info(), refund)?;
         }
+
         Ok(())
     }
 }
@@ -0,0 +1,91 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::{
+    errors::OrderBookError,
+    settlement::Settlement,
+    states::{Market, StopBook, TriggerOrders},
+    Side,
+};
+
+#[derive(Accounts)]
+pub struct CancelStopOrder<'info> {
+    #[account(mut)]
+    pub trader: Signer<'info>,
+
+    #[account(
+        seeds=[b"market", base_mint.key().as_ref(), quote_mint.key().as_ref()],
+        bump
+    )]
+    pub market: Account<'info, Market>,
+
+    pub base_mint: InterfaceAccount<'info, Mint>,
+
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, seeds=[b"market_triggerOrders", market.key().as_ref()], bump)]
+    pub trigger_orders: AccountLoader<'info, TriggerOrders>,
+
+    #[account(
+        mut,
+        associated_token::mint = base_mint,
+        associated_token::authority = market,
+        associated_token::token_program = token_program
+    )]
+    pub base_mint_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = quote_mint,
+        associated_token::authority = market,
+        associated_token::token_program = token_program
+    )]
+    pub quote_mint_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub trader_base_mint_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub trader_quote_mint_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> CancelStopOrder<'info> {
+    /// Removes a stop order that has not triggered yet and refunds its escrow.
+    pub fn cancel_stop_order(&mut self, side: Side, order_id: u64, bump: u8) -> Result<()> {
+        let stop = StopBook::load(&self.trigger_orders)?
+            .remove_order(&side, &self.trader.key(), order_id)
+            .ok_or(OrderBookError::OrderNotFound)?;
+
+        let base_mint_key = self.base_mint.key();
+        let quote_mint_key = self.quote_mint.key();
+        let seeds = &[
+            b"market",
+            base_mint_key.as_ref(),
+            quote_mint_key.as_ref(),
+            &[bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let settlement = Settlement {
+            market: &self.market,
+            base_mint: &self.base_mint,
+            quote_mint: &self.quote_mint,
+            base_mint_vault: &self.base_mint_vault,
+            quote_mint_vault: &self.quote_mint_vault,
+            token_program: &self.token_program,
+            signer_seeds,
+        };
+
+        let escrow = settlement.escrow_of(&side, &stop.to_limit_order())?;
+        match side {
+            Side::Bid => {
+                settlement.pay_quote(self.trader_quote_mint_account.to_account_info(), escrow)
+            }
+            Side::Ask => {
+                settlement.pay_base(self.trader_base_mint_account.to_account_info(), escrow)
+            }
+        }
+    }
+}
@@ -5,9 +5,10 @@ use anchor_spl::{
     token_interface::{Mint, TokenAccount, TokenInterface},
 };
 
-use crate::states::*;
+use crate::{errors::OrderBookError, states::*};
 
 #[derive(Accounts)]
+#[instruction(fee_bps: u16, matching: MatchingAlgorithm, capacity: u32)]
 pub struct CreateMarket<'info> {
     #[account(mut)]
     pub market_signer: Signer<'info>,
@@ -27,8 +28,14 @@ pub struct CreateMarket<'info> {
     )]
     pub market: Account<'info, Market>,
 
-    #[account(init_if_needed, payer=market_signer, space = 8 + OrderBook::INIT_SPACE , seeds = [b"market_orderBook", market.key().as_ref()], bump)]
-    pub order_book: Account<'info, OrderBook>,
+    #[account(init, payer = market_signer, space = 8 + OrderBookSide::space(capacity), seeds = [b"market_bids", market.key().as_ref()], bump)]
+    pub bids: AccountLoader<'info, OrderBookSide>,
+
+    #[account(init, payer = market_signer, space = 8 + OrderBookSide::space(capacity), seeds = [b"market_asks", market.key().as_ref()], bump)]
+    pub asks: AccountLoader<'info, OrderBookSide>,
+
+    #[account(init, payer = market_signer, space = 8 + TriggerOrders::space(capacity), seeds = [b"market_triggerOrders", market.key().as_ref()], bump)]
+    pub trigger_orders: AccountLoader<'info, TriggerOrders>,
     // Vault which hold hte base_mint
     #[account(init_if_needed,payer= market_signer, associated_token::mint= base_mint, associated_token::authority = market,
     associated_token::token_program = token_program)]
@@ -44,18 +51,36 @@ pub struct CreateMarket<'info> {
 }
 
 impl<'info> CreateMarket<'info> {
-    pub fn create_market(&mut self, fee_bps: u16) -> Result<()> {
+    pub fn create_market(
+        &mut self,
+        fee_bps: u16,
+        matching: MatchingAlgorithm,
+        capacity: u32,
+    ) -> Result<()> {
+        require!(
+            capacity > 0 && capacity <= OrderBookSide::MAX_INITIAL_CAPACITY,
+            OrderBookError::ErrorValueInvalid
+        );
+
         let markett = &mut self.market;
         markett.fee_bps = fee_bps;
+        markett.matching = matching;
         markett.base_mint = self.base_mint.key();
         markett.quote_mint = self.quote_mint.key();
         markett.creator = self.market_signer.key();
         markett.base_vault = self.base_mint_vault.key();
         markett.quote_vault = self.quote_vault.key();
 
-        let order_book = &mut self.order_book;
+        for (book_side, first_order_id) in [(&self.bids, 2), (&self.asks, 1)] {
+            let mut book_side = book_side.load_init()?;
+            book_side.market = markett.key();
+            book_side.next_order_id = first_order_id;
+            book_side.capacity = capacity;
+        }
 
-        order_book.market = markett.key();
+        let mut trigger_orders = self.trigger_orders.load_init()?;
+        trigger_orders.market = markett.key();
+        trigger_orders.capacity = capacity;
 
         Ok(())
     }
@@ -1,10 +1,12 @@
 use std::cmp::min;
 
-use crate::errors::OrderBookError;
-use crate::{Market, OrderBook};
+use crate::settlement::{find_ata, Settlement};
+use crate::{
+    Book, LimitOrder, Market, MatchingAlgorithm, OrderBookSide, SelfTradeBehavior, Side,
+    StopBook, StopKind, StopOrder, TriggerOrders,
+};
 use anchor_lang::prelude::*;
-use anchor_spl::associated_token::get_associated_token_address;
-use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
 #[derive(Accounts)]
 pub struct MatchOrders<'info> {
@@ -20,14 +22,25 @@ pub struct MatchOrders<'info> {
     #[account(mut)]
     pub quote_mint_vault: InterfaceAccount<'info, TokenAccount>,
 
-    #[account(mut ,seeds=[b"market", base_mint.key().as_ref(), quote_mint.key().as_ref()],bump
+    #[account(seeds=[b"market", base_mint.key().as_ref(), quote_mint.key().as_ref()],bump
  )]
     pub market: Account<'info, Market>,
 
-    #[account(mut , seeds = [b"market_orderBook", market.key().as_ref()], bump)]
-    pub order_book: Account<'info, OrderBook>,
+    #[account(mut, seeds = [b"market_bids", market.key().as_ref()], bump)]
+    pub bids: AccountLoader<'info, OrderBookSide>,
 
-    #[account(mut)]
+    #[account(mut, seeds = [b"market_asks", market.key().as_ref()], bump)]
+    pub asks: AccountLoader<'info, OrderBookSide>,
+
+    #[account(mut , seeds = [b"market_triggerOrders", market.key().as_ref()], bump)]
+    pub trigger_orders: AccountLoader<'info, TriggerOrders>,
+
+    #[account(
+        mut,
+        associated_token::mint = quote_mint,
+        associated_token::authority = market.creator,
+        associated_token::token_program = token_program
+    )]
     pub fee_collector: InterfaceAccount<'info, TokenAccount>,
     pub token_program: Interface<'info, TokenInterface>,
 }
@@ -38,9 +51,6 @@ impl<'info> MatchOrders<'info> {
         bump: u8,
         remaining_accounts: &'info [AccountInfo<'info>],
     ) -> Result<()> {
-        self.order_book.bids.sort_by(|a, b| b.price.cmp(&a.price));
-        self.order_book.asks.sort_by(|a, b| a.price.cmp(&b.price));
-
         let base_mint_key = self.base_mint.key();
         let quote_mint_key = self.quote_mint.key();
         let seeds = &[
@@ -54,128 +64,145 @@ impl<'info> MatchOrders<'info> {
 
         let signer_seeds = &[&seeds[..]];
 
-        while !self.order_book.bids.is_empty() && !self.order_book.asks.is_empty() {
-            let bid = &self.order_book.bids[0];
-            let ask = &self.order_book.asks[0];
+        let settlement = Settlement {
+            market: &self.market,
+            base_mint: &self.base_mint,
+            quote_mint: &self.quote_mint,
+            base_mint_vault: &self.base_mint_vault,
+            quote_mint_vault: &self.quote_mint_vault,
+            token_program: &self.token_program,
+            signer_seeds,
+        };
+
+        let now = Clock::get()?.unix_timestamp;
+        let mut order_book = Book::load(&self.bids, &self.asks)?;
+        let mut stops = StopBook::load(&self.trigger_orders)?;
+
+        fire_triggered_stops(
+            &mut order_book,
+            &mut stops,
+            &settlement,
+            self.fee_collector.to_account_info(),
+            now,
+            remaining,
+        )?;
+
+        while let (Some(&bid), Some(&ask)) = (order_book.bids.best(), order_book.asks.best()) {
+            // Expired orders never match, refund them, and the other leg of a linked one, as
+            // the crank runs into them
+            if let Some((side, expired)) = [(Side::Bid, bid), (Side::Ask, ask)]
+                .into_iter()
+                .find(|(_, order)| order.is_expired(now))
+            {
+                order_book.remove_order(&side, &expired.owner, expired.order_id);
+                msg!("Refunding expired order_id={}", { expired.order_id });
+                settlement.refund_order(&side, &expired, remaining)?;
+                if let Some((side, linked)) = order_book.remove_linked(&expired) {
+                    msg!("One-cancels-other cancelled order_id={}", {
+                        linked.order_id
+                    });
+                    settlement.refund_order(&side, &linked, remaining)?;
+                }
+                continue;
+            }
+
+            let references = order_book.peg_references();
+            let (Some(bid_price), Some(ask_price)) = (
+                references.price_of(&Side::Bid, &bid),
+                references.price_of(&Side::Ask, &ask),
+            ) else {
+                break;
+            };
 
             msg!(
                 "Checking match: bid.price={}, ask.price={}",
-                bid.price,
-                ask.price
+                bid_price,
+                ask_price
             );
-            msg!("Bid amount={}, Ask amount={}", bid.amount, ask.amount);
-
-            if bid.price >= ask.price {
-                let fill_amount = min(bid.amount, ask.amount);
-                let execution_price = ask.price;
-
-                // Calculate quote amount: (fill_amount * execution_price) / 10^base_decimals
-                // This normalizes the result to quote token units (same fix as place_order.rs)
-                let base_scale = 10u64.pow(self.base_mint.decimals as u32);
-                let quote_amount = fill_amount
-                    .checked_mul(execution_price)
-                    .ok_or(OrderBookError::ErrorInMultiply)?
-                    .checked_div(base_scale)
-                    .ok_or(OrderBookError::ErrorInMultiply)?;
-
-                //Calculate the fee
-                let total_fee = (quote_amount * self.market.fee_bps as u64) / 10000;
-                let asker_fee = total_fee;
-
-                let bidder_base_ata = find_ata(remaining, bid.owner, self.base_mint.key())?;
-                let asker_quote_ata = find_ata(remaining, ask.owner, self.quote_mint.key())?;
-
-                let cranker_quote_ata =
-                    find_ata(remaining, self.cranker.key(), self.quote_mint.key())?;
-
-                //TokenTransfer from vault to asker
-
-                let ctx_quote_acc = TransferChecked {
-                    from: self.quote_mint_vault.to_account_info(),
-                    to: asker_quote_ata.to_account_info(),
-                    mint: self.quote_mint.to_account_info(),
-                    authority: self.market.to_account_info(),
-                };
-
-                let ctx_quote =
-                    CpiContext::new(self.token_program.to_account_info(), ctx_quote_acc)
-                        .with_signer(signer_seeds);
-
-                token_interface::transfer_checked(
-                    ctx_quote,
-                    (quote_amount - asker_fee),
-                    self.quote_mint.decimals,
-                )?;
-
-                let ctx_base_acc = TransferChecked {
-                    from: self.base_mint_vault.to_account_info(),
-                    to: bidder_base_ata.to_account_info(),
-                    mint: self.base_mint.to_account_info(),
-                    authority: self.market.to_account_info(),
-                };
-
-                let ctx_base = CpiContext::new(self.token_program.to_account_info(), ctx_base_acc)
-                    .with_signer(signer_seeds);
-
-                token_interface::transfer_checked(ctx_base, fill_amount, self.base_mint.decimals)?;
-
-                self.order_book.bids[0].amount -= fill_amount;
-                self.order_book.asks[0].amount -= fill_amount;
-
-                if self.order_book.bids[0].amount == 0 {
-                    self.order_book.bids.remove(0);
-                }
-                if self.order_book.asks[0].amount == 0 {
-                    self.order_book.asks.remove(0);
+            msg!("Bid amount={}, Ask amount={}", { bid.amount }, {
+                ask.amount
+            });
+
+            if bid_price >= ask_price {
+                if bid.owner == ask.owner {
+                    for (side, cancelled) in order_book.prevent_top_self_trade() {
+                        msg!(
+                            "Self-trade prevention cancelled {} of order_id={}",
+                            { cancelled.amount },
+                            { cancelled.order_id }
+                        );
+                        settlement.refund_order(&side, &cancelled, remaining)?;
+                    }
+                    continue;
                 }
 
-                let cranker_reward = total_fee / 10;
-                let market_fee = total_fee - cranker_reward;
-
-                let ctx_cranker = TransferChecked {
-                    from: self.quote_mint_vault.to_account_info(),
-                    to: cranker_quote_ata.to_account_info(),
-                    mint: self.quote_mint.to_account_info(),
-                    authority: self.market.to_account_info(),
+                // Two resting orders cross and the newer one counts as the taker. A pro-rata
+                // market shares its amount across the other side's best level as `take`
+                // does, otherwise they fill one on one in price-time order
+                let execution_price = ask_price;
+                let crosses = match self.market.matching {
+                    MatchingAlgorithm::ProRata { top_order_priority } => {
+                        let taker_side = order_book.top_taker_side();
+                        let taker = match taker_side {
+                            Side::Bid => bid,
+                            Side::Ask => ask,
+                        };
+                        order_book
+                            .share_top_level(
+                                &taker_side,
+                                taker.amount,
+                                top_order_priority,
+                                now,
+                                &taker.owner,
+                            )
+                            .into_iter()
+                            .map(|(maker, fill_amount)| match taker_side {
+                                Side::Bid => (bid, maker, fill_amount),
+                                Side::Ask => (maker, ask, fill_amount),
+                            })
+                            .collect()
+                    }
+                    MatchingAlgorithm::Fifo => vec![(bid, ask, min(bid.amount, ask.amount))],
                 };
+                for (bid, ask, fill_amount) in crosses {
+                    // A fill can cancel the linked leg of a maker further along the level
+                    if order_book.bids.get(bid.order_id).is_none()
+                        || order_book.asks.get(ask.order_id).is_none()
+                    {
+                        continue;
+                    }
+                    self.fill_resting(
+                        &mut order_book,
+                        &mut stops,
+                        &settlement,
+                        &bid,
+                        &ask,
+                        fill_amount,
+                        execution_price,
+                        remaining,
+                    )?;
+                }
 
-                let ctx_cranker_transfer =
-                    CpiContext::new(self.token_program.to_account_info(), ctx_cranker)
-                        .with_signer(signer_seeds);
-
-                token_interface::transfer_checked(
-                    ctx_cranker_transfer,
-                    cranker_reward,
-                    self.quote_mint.decimals,
+                fire_triggered_stops(
+                    &mut order_book,
+                    &mut stops,
+                    &settlement,
+                    self.fee_collector.to_account_info(),
+                    now,
+                    remaining,
                 )?;
 
-                let ctx_fee = TransferChecked {
-                    from: self.quote_mint_vault.to_account_info(),
-                    to: self.fee_collector.to_account_info(),
-                    mint: self.quote_mint.to_account_info(),
-                    authority: self.market.to_account_info(),
-                };
-
-                let ctx_fee_transfer =
-                    CpiContext::new(self.token_program.to_account_info(), ctx_fee)
-                        .with_signer(signer_seeds);
-
-                token_interface::transfer_checked(
-                    ctx_fee_transfer,
-                    market_fee,
-                    self.quote_mint.decimals,
-                )?;
-                if self.order_book.bids.len() > 0 && self.order_book.asks.len() > 0 {
+                if let (Some(bid), Some(ask)) = (order_book.bids.best(), order_book.asks.best()) {
                     msg!(
                         "After match - Bid amount={}, Ask amount={}",
-                        self.order_book.bids[0].amount,
-                        self.order_book.asks[0].amount
+                        { bid.amount },
+                        { ask.amount }
                     );
                 };
                 msg!(
                     "Bids count={}, Asks count={}",
-                    self.order_book.bids.len(),
-                    self.order_book.asks.len()
+                    order_book.bids.len(),
+                    order_book.asks.len()
                 );
             } else {
                 break;
@@ -184,20 +211,180 @@ impl<'info> MatchOrders<'info> {
 
         Ok(())
     }
+
+    /// Fills `fill_amount` between resting `bid` and `ask` at `execution_price`, paying the
+    /// cranker its share of the fee. Either leaves the book once exhausted, and a fill on a
+    /// leg of a one-cancels-other pair cancels the other.
+    #[allow(clippy::too_many_arguments)]
+    fn fill_resting(
+        &self,
+        order_book: &mut Book,
+        stops: &mut StopBook,
+        settlement: &Settlement<'_, 'info>,
+        bid: &LimitOrder,
+        ask: &LimitOrder,
+        fill_amount: u64,
+        execution_price: u64,
+        remaining: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let bidder_base_ata = find_ata(remaining, bid.owner, self.base_mint.key())?;
+        let asker_quote_ata = find_ata(remaining, ask.owner, self.quote_mint.key())?;
+
+        let cranker_quote_ata = find_ata(remaining, self.cranker.key(), self.quote_mint.key())?;
+
+        settlement.settle_fill(
+            fill_amount,
+            execution_price,
+            bidder_base_ata.to_account_info(),
+            asker_quote_ata.to_account_info(),
+            self.fee_collector.to_account_info(),
+            Some(cranker_quote_ata.to_account_info()),
+        )?;
+
+        // The bid escrowed at its own price, give back what it did not need
+        settlement.refund_price_improvement(
+            bid.owner,
+            fill_amount,
+            bid.price,
+            execution_price,
+            remaining,
+        )?;
+        stops.record_trade(execution_price);
+
+        for (side, order) in [(Side::Bid, bid), (Side::Ask, ask)] {
+            let resting = order_book.order_mut(&order.owner, order.order_id).unwrap();
+            resting.linked_order_id = 0;
+            resting.amount -= fill_amount;
+            if resting.amount == 0 {
+                order_book.remove_or_refill(&side, order.order_id);
+            }
+        }
+
+        for order in [bid, ask] {
+            if let Some((side, linked)) = order_book.remove_linked(order) {
+                msg!("One-cancels-other cancelled order_id={}", {
+                    linked.order_id
+                });
+                settlement.refund_order(&side, &linked, remaining)?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
-fn find_ata<'info>(
-    accounts: &'info [AccountInfo<'info>],
-    owner: Pubkey,
-    mint: Pubkey,
-) -> Result<InterfaceAccount<'info, TokenAccount>> {
-    let expected_ata = get_associated_token_address(&owner, &mint);
+/// Fires every triggered stop order; one stop's fills can trigger the next. A stop whose
+/// accounts are not all in `remaining` stays triggered for a later trade or crank.
+pub(crate) fn fire_triggered_stops<'info>(
+    order_book: &mut Book,
+    stops: &mut StopBook,
+    settlement: &Settlement<'_, 'info>,
+    fee_collector: AccountInfo<'info>,
+    now: i64,
+    remaining: &'info [AccountInfo<'info>],
+) -> Result<()> {
+    while let Some((side, stop)) = stops.pop_triggered(|side, stop| {
+        stop_accounts_passed(order_book, side, stop, settlement, now, remaining)
+    }) {
+        msg!(
+            "Stop order_id={} triggered at {}",
+            { stop.order_id },
+            stops.last_traded_price()
+        );
+
+        let take = order_book.take(
+            &side,
+            stop.price,
+            stop.amount,
+            now,
+            &stop.owner,
+            SelfTradeBehavior::CancelTaker,
+            settlement.market.matching,
+        );
+        for fill in &take.fills {
+            stops.record_trade(fill.price);
+        }
 
-    for account in accounts {
-        if account.key() == expected_ata {
-            return InterfaceAccount::try_from(account);
+        let book = match side {
+            Side::Bid => &order_book.bids,
+            Side::Ask => &order_book.asks,
+        };
+        let resting = if stop.kind() == StopKind::StopLimit && !book.is_full() {
+            take.remaining
+        } else {
+            0
+        };
+        if resting > 0 {
+            order_book.insert_order(
+                &side,
+                LimitOrder {
+                    amount: resting,
+                    ..stop.to_limit_order()
+                },
+            );
         }
+
+        let owner_ata = |mint: Pubkey| {
+            find_ata(remaining, stop.owner, mint)
+                .ok()
+                .map(|ata| ata.to_account_info())
+        };
+        settlement.settle_take(
+            &side,
+            stop.price,
+            settlement.escrow_of(&side, &stop.to_limit_order())?,
+            &take,
+            resting,
+            owner_ata(settlement.base_mint.key()),
+            owner_ata(settlement.quote_mint.key()),
+            fee_collector.clone(),
+            remaining,
+        )?;
     }
 
-    err!(OrderBookError::AtaNotFound)
+    Ok(())
+}
+
+/// Whether `remaining` holds every ATA that firing `stop` on `side` now can pay into: the
+/// owner's for its proceeds and for a refund if one can be left, and those of the makers it
+/// can reach, see `Book::reach`.
+fn stop_accounts_passed<'info>(
+    order_book: &Book,
+    side: &Side,
+    stop: &StopOrder,
+    settlement: &Settlement<'_, 'info>,
+    now: i64,
+    remaining: &'info [AccountInfo<'info>],
+) -> bool {
+    let base = settlement.base_mint.key();
+    let quote = settlement.quote_mint.key();
+    let passed = |owner: Pubkey, mint: Pubkey| find_ata(remaining, owner, mint).is_ok();
+    let escrow_mint = |side: &Side| match side {
+        Side::Bid => quote,
+        Side::Ask => base,
+    };
+
+    let reach = order_book.reach(side, stop.price, stop.amount, now, &stop.owner);
+    let rests = stop.kind() == StopKind::StopLimit && !order_book.book(side).is_full();
+    let improved = *side == Side::Bid && reach.makers.iter().any(|(_, price)| *price < stop.price);
+    let refunds = reach.self_trade || (!reach.fills_in_full && !rests) || improved;
+
+    if !reach.makers.is_empty() && !passed(stop.owner, escrow_mint(&side.opposite())) {
+        return false;
+    }
+    if refunds && !passed(stop.owner, escrow_mint(side)) {
+        return false;
+    }
+    reach.makers.iter().all(|(maker, price)| {
+        let paid = match side {
+            Side::Bid => passed(maker.owner, quote),
+            // Pegged bids filled below their limit get the difference back in quote
+            Side::Ask => {
+                passed(maker.owner, base) && (*price >= maker.price || passed(maker.owner, quote))
+            }
+        };
+        let linked = maker.linked_order_id == 0
+            || passed(maker.owner, escrow_mint(&Side::of_order(maker.linked_order_id)));
+        paid && linked
+    })
 }
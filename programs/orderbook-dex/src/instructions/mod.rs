@@ -1,10 +1,21 @@
+pub mod amend_order;
 pub mod cancel_order;
+pub mod cancel_stop_order;
 pub mod create_market;
 pub mod match_orders;
 pub mod place_order;
+pub mod place_stop_order;
+pub mod post_order;
+pub mod resize_order_book;
+pub mod sweep_expired_orders;
 
+pub use amend_order::*;
 pub use cancel_order::*;
-pub use create_market::*;
+pub use cancel_stop_order::*;
 pub use create_market::*;
 pub use match_orders::*;
 pub use place_order::*;
+pub use place_stop_order::*;
+pub use post_order::*;
+pub use resize_order_book::*;
+pub use sweep_expired_orders::*;
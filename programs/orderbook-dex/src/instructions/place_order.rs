@@ -6,26 +6,30 @@ use anchor_spl::{
 
 use crate::{
     errors::OrderBookError,
-    states::{LimitOrder, Market, OrderBook},
+    instructions::match_orders::fire_triggered_stops,
+    settlement::{quote_amount, Settlement},
+    states::{
+        BatchOrder, Book, Fill, LimitOrder, Market, OrderBookSide, Peg, PostOnly,
+        SelfTradeBehavior, StopBook, SwapDirection, TimeInForce, TriggerOrders,
+    },
     Side,
 };
 
+/// Locks both sides of the book, even for an order that only rests; `post_order` is the way
+/// to rest one without.
 #[derive(Accounts)]
 pub struct PlaceOrder<'info> {
     #[account(mut)]
     pub trader: Signer<'info>,
 
     #[account(
-        mut, 
         seeds=[b"market", base_mint.key().as_ref(), quote_mint.key().as_ref()], 
         bump
     )]
     pub market: Account<'info, Market>,
 
-    #[account(mut)]
     pub base_mint: InterfaceAccount<'info, Mint>,
 
-    #[account(mut)]
     pub quote_mint: InterfaceAccount<'info, Mint>,
 
     pub token_program: Interface<'info, TokenInterface>,
@@ -53,84 +57,736 @@ pub struct PlaceOrder<'info> {
     pub trader_quote_mint_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
-        mut, 
-        seeds=[b"market_orderBook", market.key().as_ref()], 
+        mut,
+        seeds = [b"market_bids", market.key().as_ref()],
+        bump
+    )]
+    pub bids: AccountLoader<'info, OrderBookSide>,
+
+    #[account(
+        mut,
+        seeds = [b"market_asks", market.key().as_ref()],
         bump
     )]
-    pub order_book: Account<'info, OrderBook>,
+    pub asks: AccountLoader<'info, OrderBookSide>,
+
+    #[account(
+        mut,
+        seeds = [b"market_triggerOrders", market.key().as_ref()],
+        bump
+    )]
+    pub trigger_orders: AccountLoader<'info, TriggerOrders>,
+
+    #[account(
+        mut,
+        associated_token::mint = quote_mint,
+        associated_token::authority = market.creator,
+        associated_token::token_program = token_program
+    )]
+    pub fee_collector: InterfaceAccount<'info, TokenAccount>,
 
     pub system_program: Program<'info, System>,
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
 impl<'info> PlaceOrder<'info> {
-    pub fn place_order(&mut self, price: u64, amount: u64, side: Side) -> Result<()> {
-        let order_book = &mut self.order_book;
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_order(
+        &mut self,
+        price: u64,
+        amount: u64,
+        side: Side,
+        time_in_force: TimeInForce,
+        post_only: PostOnly,
+        expires_at: i64,
+        client_order_id: u64,
+        self_trade_behavior: SelfTradeBehavior,
+        display_amount: u64,
+        linked_order_id: u64,
+        peg: Peg,
+        peg_offset: i64,
+        bump: u8,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            price > 0 && amount > 0 && (expires_at == 0 || expires_at > now),
+            OrderBookError::ErrorValueInvalid
+        );
+        require!(
+            client_order_id <= LimitOrder::MAX_CLIENT_ORDER_ID,
+            OrderBookError::ErrorValueInvalid
+        );
+        require!(
+            client_order_id == 0
+                || Book::load(&self.bids, &self.asks)?
+                    .find_by_client_id(&self.trader.key(), client_order_id)
+                    .is_none(),
+            OrderBookError::DuplicateClientOrderId
+        );
+        // Only orders resting in the book can be linked, a stop order waiting for its trigger
+        // cannot be a leg
+        if linked_order_id != 0 {
+            let mut order_book = Book::load(&self.bids, &self.asks)?;
+            let linked = order_book
+                .order_mut(&self.trader.key(), linked_order_id)
+                .ok_or(OrderBookError::OrderNotFound)?;
+            require!(
+                linked.linked_order_id == 0,
+                OrderBookError::OrderAlreadyLinked
+            );
+        }
+
+        // Pegged orders only ever rest, at a price that moves with the book
+        if peg != Peg::Fixed {
+            require!(
+                time_in_force == TimeInForce::GoodTillCancel && post_only == PostOnly::Disabled,
+                OrderBookError::ErrorValueInvalid
+            );
+        }
+
+        let price = match post_only {
+            PostOnly::Disabled => price,
+            _ => {
+                require!(
+                    time_in_force == TimeInForce::GoodTillCancel,
+                    OrderBookError::ErrorValueInvalid
+                );
+                self.post_only_price(&side, price, post_only, now)?
+            }
+        };
+
+        if time_in_force == TimeInForce::FillOrKill {
+            require!(
+                Book::load(&self.bids, &self.asks)?.liquidity_at(
+                    &side,
+                    price,
+                    now,
+                    &self.trader.key()
+                ) >= amount,
+                OrderBookError::InsufficientLiquidity
+            );
+        }
 
-        let order_id = order_book.next_order_id;
-        order_book.next_order_id = order_book.next_order_id.checked_add(1).unwrap();
+        let order_id = Book::load(&self.bids, &self.asks)?.next_order_id(&side);
 
-        let new_order = LimitOrder {
+        let mut order = LimitOrder {
             owner: self.trader.key(),
             price,
             amount,
             order_id,
+            client_order_id,
+            expires_at,
+            self_trade_behavior: self_trade_behavior as u8,
+            display_amount,
+            hidden_amount: 0,
+            linked_order_id: 0,
+            peg: peg as u8,
+            peg_offset,
         };
 
-        match side {
-            Side::Bid => {
+        let rest = time_in_force == TimeInForce::GoodTillCancel;
+        let (filled, remaining) = if peg == Peg::Fixed {
+            self.execute(
+                &side,
+                price,
+                amount,
+                rest,
+                self_trade_behavior,
+                bump,
+                remaining_accounts,
+            )?
+        } else {
+            let order_book = Book::load(&self.bids, &self.asks)?;
+            let references = order_book.peg_references();
+            if let Some(pegged_price) = references.price_of(&side, &order) {
                 require!(
-                    order_book.bids.len() < OrderBook::MAX_ORDERS,
-                    OrderBookError::OrderBookFull
+                    !order_book.crosses(&side, pegged_price, now),
+                    OrderBookError::PeggedOrderWouldCross
                 );
+            }
+            drop(order_book);
+
+            let escrow = match side {
+                Side::Bid => quote_amount(amount, price, self.base_mint.decimals)?,
+                Side::Ask => amount,
+            };
+            self.deposit(&side, escrow)?;
+            (0, amount)
+        };
+
+        // Self-trade prevention can stop a fill-or-kill short of the liquidity counted above
+        if time_in_force == TimeInForce::FillOrKill {
+            require!(filled == amount, OrderBookError::InsufficientLiquidity);
+        }
+
+        // Trading on the new leg cancels the existing one, otherwise the two get linked
+        let mut linked_order_id = linked_order_id;
+        if linked_order_id != 0 && filled > 0 {
+            self.cancel_linked_order(linked_order_id, bump)?;
+            linked_order_id = 0;
+        }
 
-                // Calculate quote amount: (amount * price) / 10^base_decimals
-                // This normalizes the result to quote token units
-                let base_scale = 10u64.pow(self.base_mint.decimals as u32);
-                let quote_price = amount
-                    .checked_mul(price)
-                    .ok_or(OrderBookError::ErrorInMultiply)?
-                    .checked_div(base_scale)
-                    .ok_or(OrderBookError::ErrorInMultiply)?;
-
-                let ctx_acc = TransferChecked {
-                    from: self.trader_quote_mint_account.to_account_info(),
-                    to: self.quote_mint_vault.to_account_info(),
-                    mint: self.quote_mint.to_account_info(),
-                    authority: self.trader.to_account_info(),
-                };
-
-                let ctx_quote = CpiContext::new(self.token_program.to_account_info(), ctx_acc);
-
-                token_interface::transfer_checked(
-                    ctx_quote,
-                    quote_price,
-                    self.quote_mint.decimals,
-                )?;
-
-                order_book.bids.push(new_order);
+        if remaining > 0 && rest && linked_order_id != 0 {
+            let mut order_book = Book::load(&self.bids, &self.asks)?;
+            match order_book.order_mut(&self.trader.key(), linked_order_id) {
+                Some(linked) => linked.linked_order_id = order_id,
+                None => linked_order_id = 0,
             }
-            Side::Ask => {
-                require!(
-                    order_book.asks.len() < OrderBook::MAX_ORDERS,
-                    OrderBookError::OrderBookFull
-                );
+        }
+
+        if remaining > 0 && rest {
+            order.linked_order_id = linked_order_id;
+            order.set_total_amount(remaining);
+
+            let mut order_book = Book::load(&self.bids, &self.asks)?;
+            match side {
+                Side::Bid => {
+                    require!(!order_book.bids.is_full(), OrderBookError::OrderBookFull);
+                    order_book.insert_order(&side, order);
+                }
+                Side::Ask => {
+                    require!(!order_book.asks.is_full(), OrderBookError::OrderBookFull);
+                    order_book.insert_order(&side, order);
+                }
+            }
+        }
+
+        if filled > 0 {
+            self.fire_triggered_stops(bump, remaining_accounts)?;
+        }
+
+        Ok(())
+    }
+
+    /// Rests a batch of orders in one go. Capacity is checked for the whole batch up front and
+    /// the escrow is collected in at most one base and one quote transfer. Batch orders never
+    /// take liquidity, so none of them may cross the book.
+    pub fn place_orders(&mut self, orders: Vec<BatchOrder>) -> Result<()> {
+        require!(!orders.is_empty(), OrderBookError::ErrorValueInvalid);
+
+        let new_bids = orders
+            .iter()
+            .filter(|order| order.side == Side::Bid)
+            .count();
+        let new_asks = orders.len() - new_bids;
+        let mut order_book = Book::load(&self.bids, &self.asks)?;
+        require!(
+            order_book.bids.len() + new_bids <= order_book.bids.capacity()
+                && order_book.asks.len() + new_asks <= order_book.asks.capacity(),
+            OrderBookError::OrderBookFull
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let trader = self.trader.key();
+        let mut base_escrow: u64 = 0;
+        let mut quote_escrow: u64 = 0;
+
+        for order in orders {
+            require!(
+                order.price > 0
+                    && order.amount > 0
+                    && order.client_order_id <= LimitOrder::MAX_CLIENT_ORDER_ID,
+                OrderBookError::ErrorValueInvalid
+            );
+            require!(
+                order.client_order_id == 0
+                    || order_book
+                        .find_by_client_id(&trader, order.client_order_id)
+                        .is_none(),
+                OrderBookError::DuplicateClientOrderId
+            );
+            require!(
+                !order_book.crosses(&order.side, order.price, now),
+                OrderBookError::BatchOrderWouldCross
+            );
+
+            let order_id = order_book.next_order_id(&order.side);
+
+            let resting = LimitOrder {
+                owner: trader,
+                price: order.price,
+                amount: order.amount,
+                order_id,
+                client_order_id: order.client_order_id,
+                expires_at: 0,
+                // Batch orders never take, they only ever meet a self-trade as the maker
+                self_trade_behavior: SelfTradeBehavior::CancelTaker as u8,
+                display_amount: 0,
+                hidden_amount: 0,
+                linked_order_id: 0,
+                peg: Peg::Fixed as u8,
+                peg_offset: 0,
+            };
+
+            match order.side {
+                Side::Bid => {
+                    let quote = quote_amount(order.amount, order.price, self.base_mint.decimals)?;
+                    quote_escrow = quote_escrow
+                        .checked_add(quote)
+                        .ok_or(OrderBookError::ErrorInMultiply)?;
+                    order_book.insert_order(&Side::Bid, resting);
+                }
+                Side::Ask => {
+                    base_escrow = base_escrow
+                        .checked_add(order.amount)
+                        .ok_or(OrderBookError::ErrorInMultiply)?;
+                    order_book.insert_order(&Side::Ask, resting);
+                }
+            }
+        }
+        drop(order_book);
+
+        if quote_escrow > 0 {
+            self.deposit(&Side::Bid, quote_escrow)?;
+        }
+        if base_escrow > 0 {
+            self.deposit(&Side::Ask, base_escrow)?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves escrow from the trader into the vault: quote for bids, base for asks.
+    fn deposit(&self, side: &Side, amount: u64) -> Result<()> {
+        let (from, to, mint, decimals) = match side {
+            Side::Bid => (
+                &self.trader_quote_mint_account,
+                &self.quote_mint_vault,
+                &self.quote_mint,
+                self.quote_mint.decimals,
+            ),
+            Side::Ask => (
+                &self.trader_base_mint_account,
+                &self.base_mint_vault,
+                &self.base_mint,
+                self.base_mint.decimals,
+            ),
+        };
+
+        let ctx_acc = TransferChecked {
+            from: from.to_account_info(),
+            to: to.to_account_info(),
+            mint: mint.to_account_info(),
+            authority: self.trader.to_account_info(),
+        };
+
+        let ctx = CpiContext::new(self.token_program.to_account_info(), ctx_acc);
+
+        token_interface::transfer_checked(ctx, amount, decimals)
+    }
 
-                let ctx_acc = TransferChecked {
-                    from: self.trader_base_mint_account.to_account_info(),
-                    to: self.base_mint_vault.to_account_info(),
-                    mint: self.base_mint.to_account_info(),
-                    authority: self.trader.to_account_info(),
-                };
+    /// Removes the trader's order `order_id`, the existing leg of a one-cancels-other pair,
+    /// and refunds its escrow.
+    fn cancel_linked_order(&mut self, order_id: u64, bump: u8) -> Result<()> {
+        let trader = self.trader.key();
+        let mut order_book = Book::load(&self.bids, &self.asks)?;
+        let Some((side, linked)) = [Side::Bid, Side::Ask].into_iter().find_map(|side| {
+            order_book
+                .remove_order(&side, &trader, order_id)
+                .map(|linked| (side, linked))
+        }) else {
+            return Ok(());
+        };
+        drop(order_book);
+        msg!("One-cancels-other cancelled order_id={}", order_id);
 
-                let ctx = CpiContext::new(self.token_program.to_account_info(), ctx_acc);
+        let base_mint_key = self.base_mint.key();
+        let quote_mint_key = self.quote_mint.key();
+        let seeds = &[
+            b"market",
+            base_mint_key.as_ref(),
+            quote_mint_key.as_ref(),
+            &[bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
 
-                token_interface::transfer_checked(ctx, amount, self.base_mint.decimals)?;
+        let settlement = Settlement {
+            market: &self.market,
+            base_mint: &self.base_mint,
+            quote_mint: &self.quote_mint,
+            base_mint_vault: &self.base_mint_vault,
+            quote_mint_vault: &self.quote_mint_vault,
+            token_program: &self.token_program,
+            signer_seeds,
+        };
 
-                order_book.asks.push(new_order);
+        let refund = settlement.escrow_of(&side, &linked)?;
+        match side {
+            Side::Bid => {
+                settlement.pay_quote(self.trader_quote_mint_account.to_account_info(), refund)
             }
+            Side::Ask => {
+                settlement.pay_base(self.trader_base_mint_account.to_account_info(), refund)
+            }
+        }
+    }
+
+    /// Price a post-only order can rest at without taking: unchanged if it does not cross,
+    /// otherwise rejected or slid to one tick behind the opposite best price.
+    fn post_only_price(
+        &self,
+        side: &Side,
+        price: u64,
+        post_only: PostOnly,
+        now: i64,
+    ) -> Result<u64> {
+        let order_book = Book::load(&self.bids, &self.asks)?;
+        let crossing_price = match side {
+            Side::Bid => order_book
+                .best_price(&Side::Ask, now)
+                .filter(|best_ask| price >= *best_ask)
+                .map(|best_ask| best_ask - 1),
+            Side::Ask => order_book
+                .best_price(&Side::Bid, now)
+                .filter(|best_bid| price <= *best_bid)
+                .map(|best_bid| best_bid + 1),
+        };
+
+        match crossing_price {
+            None => Ok(price),
+            Some(slid_price) if post_only == PostOnly::Slide && slid_price > 0 => {
+                msg!("Post-only order slid from {} to {}", price, slid_price);
+                Ok(slid_price)
+            }
+            Some(_) => err!(OrderBookError::PostOnlyWouldCross),
+        }
+    }
+
+    /// Sweeps the opposite side until `amount` is filled or the next price is worse than
+    /// `worst_price`. Market orders never rest, any unfilled part is refunded.
+    pub fn place_market_order(
+        &mut self,
+        amount: u64,
+        worst_price: u64,
+        side: Side,
+        self_trade_behavior: SelfTradeBehavior,
+        bump: u8,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        require!(
+            amount > 0 && worst_price > 0,
+            OrderBookError::ErrorValueInvalid
+        );
+
+        let (filled, _) = self.execute(
+            &side,
+            worst_price,
+            amount,
+            false,
+            self_trade_behavior,
+            bump,
+            remaining_accounts,
+        )?;
+        msg!("Market order filled {} of {}", filled, amount);
+
+        if filled > 0 {
+            self.fire_triggered_stops(bump, remaining_accounts)?;
+        }
+
+        Ok(())
+    }
+
+    /// Market buy sized in quote: sweeps the asks up to `worst_price` until `quote_budget` is
+    /// spent. Whatever is left, down to dust too small for one more base unit, is refunded.
+    pub fn place_quote_market_order(
+        &mut self,
+        quote_budget: u64,
+        worst_price: u64,
+        self_trade_behavior: SelfTradeBehavior,
+        bump: u8,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        require!(
+            quote_budget > 0 && worst_price > 0,
+            OrderBookError::ErrorValueInvalid
+        );
+
+        self.deposit(&Side::Bid, quote_budget)?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let take = Book::load(&self.bids, &self.asks)?.take_quote(
+            worst_price,
+            quote_budget,
+            self.base_mint.decimals,
+            now,
+            &self.trader.key(),
+            self_trade_behavior,
+            self.market.matching,
+        );
+
+        let base_mint_key = self.base_mint.key();
+        let quote_mint_key = self.quote_mint.key();
+        let seeds = &[
+            b"market",
+            base_mint_key.as_ref(),
+            quote_mint_key.as_ref(),
+            &[bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let settlement = Settlement {
+            market: &self.market,
+            base_mint: &self.base_mint,
+            quote_mint: &self.quote_mint,
+            base_mint_vault: &self.base_mint_vault,
+            quote_mint_vault: &self.quote_mint_vault,
+            token_program: &self.token_program,
+            signer_seeds,
         };
 
+        settlement.settle_take(
+            &Side::Bid,
+            worst_price,
+            quote_budget,
+            &take,
+            0,
+            Some(self.trader_base_mint_account.to_account_info()),
+            Some(self.trader_quote_mint_account.to_account_info()),
+            self.fee_collector.to_account_info(),
+            remaining_accounts,
+        )?;
+
+        let bought: u64 = take.fills.iter().map(|fill| fill.amount).sum();
+        msg!(
+            "Quote market order bought {}, {} of {} quote left unspent",
+            bought,
+            take.remaining,
+            quote_budget
+        );
+
+        if !take.fills.is_empty() {
+            self.record_trades(&take.fills)?;
+            self.fire_triggered_stops(bump, remaining_accounts)?;
+        }
+
         Ok(())
     }
+
+    /// Exact-in swap against the book at whatever prices it offers: all of `amount_in` is
+    /// offered and fails unless at least `min_amount_out` comes back, net of fees. Unspent
+    /// input is refunded. Meeting one of the trader's own orders ends the swap there.
+    pub fn swap(
+        &mut self,
+        amount_in: u64,
+        min_amount_out: u64,
+        direction: SwapDirection,
+        bump: u8,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        require!(amount_in > 0, OrderBookError::ErrorValueInvalid);
+
+        let now = Clock::get()?.unix_timestamp;
+        let trader = self.trader.key();
+        let (side, limit_price, take) = match direction {
+            SwapDirection::QuoteToBase => (
+                Side::Bid,
+                u64::MAX,
+                Book::load(&self.bids, &self.asks)?.take_quote(
+                    u64::MAX,
+                    amount_in,
+                    self.base_mint.decimals,
+                    now,
+                    &trader,
+                    SelfTradeBehavior::CancelTaker,
+                    self.market.matching,
+                ),
+            ),
+            SwapDirection::BaseToQuote => (
+                Side::Ask,
+                1,
+                Book::load(&self.bids, &self.asks)?.take(
+                    &Side::Ask,
+                    1,
+                    amount_in,
+                    now,
+                    &trader,
+                    SelfTradeBehavior::CancelTaker,
+                    self.market.matching,
+                ),
+            ),
+        };
+
+        let base_mint_key = self.base_mint.key();
+        let quote_mint_key = self.quote_mint.key();
+        let seeds = &[
+            b"market",
+            base_mint_key.as_ref(),
+            quote_mint_key.as_ref(),
+            &[bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let settlement = Settlement {
+            market: &self.market,
+            base_mint: &self.base_mint,
+            quote_mint: &self.quote_mint,
+            base_mint_vault: &self.base_mint_vault,
+            quote_mint_vault: &self.quote_mint_vault,
+            token_program: &self.token_program,
+            signer_seeds,
+        };
+
+        let mut amount_out: u64 = 0;
+        for fill in &take.fills {
+            let out = match side {
+                Side::Bid => fill.amount,
+                Side::Ask => {
+                    let quote = quote_amount(fill.amount, fill.price, self.base_mint.decimals)?;
+                    quote - settlement.fee_of(quote)?
+                }
+            };
+            amount_out = amount_out
+                .checked_add(out)
+                .ok_or(OrderBookError::ErrorInMultiply)?;
+        }
+        require!(
+            amount_out >= min_amount_out,
+            OrderBookError::SlippageExceeded
+        );
+
+        self.deposit(&side, amount_in)?;
+        settlement.settle_take(
+            &side,
+            limit_price,
+            amount_in,
+            &take,
+            0,
+            Some(self.trader_base_mint_account.to_account_info()),
+            Some(self.trader_quote_mint_account.to_account_info()),
+            self.fee_collector.to_account_info(),
+            remaining_accounts,
+        )?;
+        msg!("Swapped {} in for {} out", amount_in, amount_out);
+
+        if !take.fills.is_empty() {
+            self.record_trades(&take.fills)?;
+            self.fire_triggered_stops(bump, remaining_accounts)?;
+        }
+
+        Ok(())
+    }
+
+    /// Escrows `amount` at `price`, fills whatever crosses and returns the filled amount and
+    /// the unfilled amount still live. Escrow for the live remainder stays in the vault when
+    /// `rest` is set and is refunded otherwise, together with any price improvement a bid got
+    /// from the makers and whatever self-trade prevention cancelled. The fills are recorded
+    /// as trades, the stop orders they trigger are left to `fire_triggered_stops`.
+    #[allow(clippy::too_many_arguments)]
+    fn execute(
+        &mut self,
+        side: &Side,
+        price: u64,
+        amount: u64,
+        rest: bool,
+        self_trade_behavior: SelfTradeBehavior,
+        bump: u8,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<(u64, u64)> {
+        let base_mint_key = self.base_mint.key();
+        let quote_mint_key = self.quote_mint.key();
+        let seeds = &[
+            b"market",
+            base_mint_key.as_ref(),
+            quote_mint_key.as_ref(),
+            &[bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let escrow = match side {
+            Side::Bid => quote_amount(amount, price, self.base_mint.decimals)?,
+            Side::Ask => amount,
+        };
+        self.deposit(side, escrow)?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let take = Book::load(&self.bids, &self.asks)?.take(
+            side,
+            price,
+            amount,
+            now,
+            &self.trader.key(),
+            self_trade_behavior,
+            self.market.matching,
+        );
+        let filled: u64 = take.fills.iter().map(|fill| fill.amount).sum();
+        let remaining = take.remaining;
+
+        let settlement = Settlement {
+            market: &self.market,
+            base_mint: &self.base_mint,
+            quote_mint: &self.quote_mint,
+            base_mint_vault: &self.base_mint_vault,
+            quote_mint_vault: &self.quote_mint_vault,
+            token_program: &self.token_program,
+            signer_seeds,
+        };
+
+        let resting = if rest { remaining } else { 0 };
+        settlement.settle_take(
+            side,
+            price,
+            escrow,
+            &take,
+            resting,
+            Some(self.trader_base_mint_account.to_account_info()),
+            Some(self.trader_quote_mint_account.to_account_info()),
+            self.fee_collector.to_account_info(),
+            remaining_accounts,
+        )?;
+        self.record_trades(&take.fills)?;
+
+        Ok((filled, remaining))
+    }
+
+    /// Moves the last traded price, and the trailing stops with it, through `fills`. The stop
+    /// orders are only loaded when there are fills, so orders that just rest skip them.
+    fn record_trades(&self, fills: &[Fill]) -> Result<()> {
+        if fills.is_empty() {
+            return Ok(());
+        }
+        let mut stops = StopBook::load(&self.trigger_orders)?;
+        for fill in fills {
+            stops.record_trade(fill.price);
+        }
+        Ok(())
+    }
+
+    /// Fires the stop orders triggered by this instruction's trades, as the crank would.
+    /// Their owners' ATAs and those of the makers they fill against are looked up in
+    /// `remaining_accounts`.
+    fn fire_triggered_stops(
+        &mut self,
+        bump: u8,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let base_mint_key = self.base_mint.key();
+        let quote_mint_key = self.quote_mint.key();
+        let seeds = &[
+            b"market",
+            base_mint_key.as_ref(),
+            quote_mint_key.as_ref(),
+            &[bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let settlement = Settlement {
+            market: &self.market,
+            base_mint: &self.base_mint,
+            quote_mint: &self.quote_mint,
+            base_mint_vault: &self.base_mint_vault,
+            quote_mint_vault: &self.quote_mint_vault,
+            token_program: &self.token_program,
+            signer_seeds,
+        };
+
+        fire_triggered_stops(
+            &mut Book::load(&self.bids, &self.asks)?,
+            &mut StopBook::load(&self.trigger_orders)?,
+            &settlement,
+            self.fee_collector.to_account_info(),
+            Clock::get()?.unix_timestamp,
+            remaining_accounts,
+        )
+    }
 }
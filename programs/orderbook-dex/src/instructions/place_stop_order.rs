@@ -0,0 +1,126 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::{
+    errors::OrderBookError,
+    settlement::quote_amount,
+    states::{
+        Market, OrderBookSide, StopBook, StopKind, StopOrder, TrailingOffset, TriggerOrders,
+    },
+    Side,
+};
+
+#[derive(Accounts)]
+#[instruction(side: Side)]
+pub struct PlaceStopOrder<'info> {
+    #[account(mut)]
+    pub trader: Signer<'info>,
+
+    #[account(
+        seeds=[b"market", base_mint.key().as_ref(), quote_mint.key().as_ref()],
+        bump
+    )]
+    pub market: Account<'info, Market>,
+
+    pub base_mint: InterfaceAccount<'info, Mint>,
+
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    /// The side the order ends up in, which hands out its id.
+    #[account(mut, seeds = [side.book_seed(), market.key().as_ref()], bump)]
+    pub book_side: AccountLoader<'info, OrderBookSide>,
+
+    #[account(mut, seeds=[b"market_triggerOrders", market.key().as_ref()], bump)]
+    pub trigger_orders: AccountLoader<'info, TriggerOrders>,
+
+    /// Quote vault for bids, base vault for asks.
+    #[account(
+        mut,
+        address = match side {
+            Side::Bid => market.quote_vault,
+            Side::Ask => market.base_vault,
+        }
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// The trader's account of the vault's mint.
+    #[account(mut)]
+    pub trader_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> PlaceStopOrder<'info> {
+    /// Stores a stop order and escrows it at `price` right away. It fires with the trade,
+    /// taken or cranked, that moves the last traded price to `trigger_price`: rising to it
+    /// for a bid, falling to it for an ask. With `trailing` set the trigger follows the
+    /// traded price from there on, see `StopBook::record_trade`.
+    pub fn place_stop_order(
+        &mut self,
+        side: Side,
+        kind: StopKind,
+        trigger_price: u64,
+        price: u64,
+        amount: u64,
+        trailing: Option<TrailingOffset>,
+    ) -> Result<()> {
+        require!(
+            trigger_price > 0 && price > 0 && amount > 0,
+            OrderBookError::ErrorValueInvalid
+        );
+        match trailing {
+            Some(TrailingOffset::Absolute { offset }) => {
+                require!(offset > 0, OrderBookError::ErrorValueInvalid)
+            }
+            Some(TrailingOffset::Bps { bps }) => {
+                require!(bps > 0 && bps < 10000, OrderBookError::ErrorValueInvalid)
+            }
+            None => {}
+        }
+
+        let mut stops = StopBook::load(&self.trigger_orders)?;
+        require!(!stops.is_full(), OrderBookError::TriggerOrdersFull);
+
+        // Stop orders share the id sequence of the book they end up in
+        let order_id = self.book_side.load_mut()?.take_order_id();
+
+        let (escrow, mint) = match side {
+            Side::Bid => (
+                quote_amount(amount, price, self.base_mint.decimals)?,
+                &self.quote_mint,
+            ),
+            Side::Ask => (amount, &self.base_mint),
+        };
+
+        let ctx_acc = TransferChecked {
+            from: self.trader_token_account.to_account_info(),
+            to: self.vault.to_account_info(),
+            mint: mint.to_account_info(),
+            authority: self.trader.to_account_info(),
+        };
+
+        let ctx = CpiContext::new(self.token_program.to_account_info(), ctx_acc);
+
+        token_interface::transfer_checked(ctx, escrow, mint.decimals)?;
+
+        let mut stop = StopOrder {
+            owner: self.trader.key(),
+            kind: kind as u8,
+            trigger_price,
+            price,
+            amount,
+            order_id,
+            trailing: 0,
+            trailing_offset: 0,
+        };
+        stop.set_trailing(trailing);
+        stops.push(stop);
+        msg!(
+            "Stop order_id={} waiting for trigger={}",
+            order_id,
+            trigger_price
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::{
+    errors::OrderBookError,
+    settlement::quote_amount,
+    states::{LimitOrder, Market, OrderBookSide, Peg, SelfTradeBehavior, SideBook},
+    Side,
+};
+
+#[derive(Accounts)]
+#[instruction(side: Side)]
+pub struct PostOrder<'info> {
+    #[account(mut)]
+    pub trader: Signer<'info>,
+
+    #[account(
+        seeds=[b"market", base_mint.key().as_ref(), quote_mint.key().as_ref()],
+        bump
+    )]
+    pub market: Account<'info, Market>,
+
+    pub base_mint: InterfaceAccount<'info, Mint>,
+
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    /// The side the order rests on.
+    #[account(mut, seeds = [side.book_seed(), market.key().as_ref()], bump)]
+    pub book_side: AccountLoader<'info, OrderBookSide>,
+
+    /// Quote vault for bids, base vault for asks.
+    #[account(
+        mut,
+        address = match side {
+            Side::Bid => market.quote_vault,
+            Side::Ask => market.base_vault,
+        }
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// The trader's account of the vault's mint.
+    #[account(mut)]
+    pub trader_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> PostOrder<'info> {
+    /// Rests `amount` at `price` until filled or cancelled. The order is not checked against
+    /// the other side, which is not passed: one that crosses rests and fills on the next
+    /// `match_order` crank. It gets no client order id, since those have to be unique
+    /// across both sides.
+    pub fn post_order(
+        &mut self,
+        side: Side,
+        price: u64,
+        amount: u64,
+        expires_at: i64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            price > 0 && amount > 0 && (expires_at == 0 || expires_at > now),
+            OrderBookError::ErrorValueInvalid
+        );
+
+        let mut order_book = SideBook::load(side.clone(), &self.book_side)?;
+        require!(!order_book.book.is_full(), OrderBookError::OrderBookFull);
+
+        let order_id = order_book.next_order_id();
+        order_book.insert_order(LimitOrder {
+            owner: self.trader.key(),
+            price,
+            amount,
+            order_id,
+            client_order_id: 0,
+            expires_at,
+            self_trade_behavior: SelfTradeBehavior::CancelTaker as u8,
+            display_amount: 0,
+            hidden_amount: 0,
+            linked_order_id: 0,
+            peg: Peg::Fixed as u8,
+            peg_offset: 0,
+        });
+        drop(order_book);
+
+        let (escrow, mint) = match side {
+            Side::Bid => (
+                quote_amount(amount, price, self.base_mint.decimals)?,
+                &self.quote_mint,
+            ),
+            Side::Ask => (amount, &self.base_mint),
+        };
+
+        let ctx_acc = TransferChecked {
+            from: self.trader_token_account.to_account_info(),
+            to: self.vault.to_account_info(),
+            mint: mint.to_account_info(),
+            authority: self.trader.to_account_info(),
+        };
+
+        let ctx = CpiContext::new(self.token_program.to_account_info(), ctx_acc);
+
+        token_interface::transfer_checked(ctx, escrow, mint.decimals)?;
+
+        msg!("Order {} posted", order_id);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+
+use crate::{
+    errors::OrderBookError,
+    states::{Market, OrderBookSide, TriggerOrders},
+};
+
+#[derive(Accounts)]
+#[instruction(capacity: u32)]
+pub struct ResizeOrderBook<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub base_mint: InterfaceAccount<'info, Mint>,
+
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds=[b"market", base_mint.key().as_ref(), quote_mint.key().as_ref()],
+        bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [b"market_bids", market.key().as_ref()],
+        bump,
+        realloc = 8 + OrderBookSide::space(capacity),
+        realloc::payer = payer,
+        realloc::zero = false
+    )]
+    pub bids: AccountLoader<'info, OrderBookSide>,
+
+    #[account(
+        mut,
+        seeds = [b"market_asks", market.key().as_ref()],
+        bump,
+        realloc = 8 + OrderBookSide::space(capacity),
+        realloc::payer = payer,
+        realloc::zero = false
+    )]
+    pub asks: AccountLoader<'info, OrderBookSide>,
+
+    #[account(
+        mut,
+        seeds = [b"market_triggerOrders", market.key().as_ref()],
+        bump,
+        realloc = 8 + TriggerOrders::space(capacity),
+        realloc::payer = payer,
+        realloc::zero = false
+    )]
+    pub trigger_orders: AccountLoader<'info, TriggerOrders>,
+
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> ResizeOrderBook<'info> {
+    /// Raises the capacity of both sides and of the stop orders to `capacity`. The accounts
+    /// have already been grown by the `realloc` constraints; the slots of each end its
+    /// account, so they only gain room at the end.
+    pub fn resize_order_book(&mut self, capacity: u32) -> Result<()> {
+        let previous = self.bids.load()?.capacity;
+        require!(capacity > previous, OrderBookError::ErrorValueInvalid);
+
+        self.bids.load_mut()?.capacity = capacity;
+        self.asks.load_mut()?.capacity = capacity;
+        self.trigger_orders.load_mut()?.capacity = capacity;
+        msg!(
+            "Order book capacity raised from {} to {}",
+            previous,
+            capacity
+        );
+
+        Ok(())
+    }
+}
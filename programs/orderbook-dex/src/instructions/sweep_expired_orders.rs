@@ -0,0 +1,75 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+use crate::settlement::{escrow_of, Vault};
+use crate::{Market, OrderBookSide, Side, SideBook};
+
+#[derive(Accounts)]
+#[instruction(side: Side)]
+pub struct SweepExpiredOrders<'info> {
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+
+    pub base_mint: InterfaceAccount<'info, Mint>,
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(seeds=[b"market", base_mint.key().as_ref(), quote_mint.key().as_ref()], bump)]
+    pub market: Account<'info, Market>,
+
+    /// The side being swept.
+    #[account(mut, seeds = [side.book_seed(), market.key().as_ref()], bump)]
+    pub book_side: AccountLoader<'info, OrderBookSide>,
+
+    /// Quote vault for bids, base vault for asks.
+    #[account(
+        mut,
+        address = match side {
+            Side::Bid => market.quote_vault,
+            Side::Ask => market.base_vault,
+        }
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> SweepExpiredOrders<'info> {
+    pub fn sweep_expired_orders(
+        &mut self,
+        side: Side,
+        bump: u8,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let base_mint_key = self.base_mint.key();
+        let quote_mint_key = self.quote_mint.key();
+        let seeds = &[
+            b"market",
+            base_mint_key.as_ref(),
+            quote_mint_key.as_ref(),
+            &[bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let vault = Vault {
+            market: &self.market,
+            mint: match side {
+                Side::Bid => &self.quote_mint,
+                Side::Ask => &self.base_mint,
+            },
+            vault: &self.vault,
+            token_program: &self.token_program,
+            signer_seeds,
+        };
+
+        let now = Clock::get()?.unix_timestamp;
+
+        let mut order_book = SideBook::load(side.clone(), &self.book_side)?;
+        for expired in order_book.remove_expired(now) {
+            msg!("Refunding expired order_id={}", { expired.order_id });
+            let escrow = escrow_of(&side, &expired, self.base_mint.decimals)?;
+            vault.refund_order(&expired, escrow, remaining_accounts)?;
+        }
+
+        Ok(())
+    }
+}
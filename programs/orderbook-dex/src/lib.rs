@@ -1,6 +1,8 @@
 use anchor_lang::prelude::*;
+pub mod book_side;
 pub mod errors;
 pub mod instructions;
+pub mod settlement;
 pub mod states;
 pub use errors::*;
 pub use states::*;
@@ -13,28 +15,202 @@ pub mod orderbook_dex {
 
     use super::*;
 
-    pub fn create_market(ctx: Context<CreateMarket>, fee_bps: u16) -> Result<()> {
+    /// `capacity` is the room per side of the book and for stop orders.
+    pub fn create_market(
+        ctx: Context<CreateMarket>,
+        fee_bps: u16,
+        matching: MatchingAlgorithm,
+        capacity: u32,
+    ) -> Result<()> {
         msg!("Greetings from: {:?}", ctx.program_id);
-        ctx.accounts.create_market(fee_bps)?;
+        ctx.accounts.create_market(fee_bps, matching, capacity)?;
         Ok(())
     }
 
-    pub fn place_order(
-        ctx: Context<PlaceOrder>,
+    /// Grows by at most `OrderBookSide::MAX_INITIAL_CAPACITY` per call.
+    pub fn resize_order_book(ctx: Context<ResizeOrderBook>, capacity: u32) -> Result<()> {
+        ctx.accounts.resize_order_book(capacity)?;
+        Ok(())
+    }
+
+    /// Remaining accounts are the ATAs of the makers and of the stop orders its fills trigger.
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_order<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PlaceOrder<'info>>,
         price: u64,
         amount: u64,
         side: Side,
+        time_in_force: TimeInForce,
+        post_only: PostOnly,
+        expires_at: i64,
+        client_order_id: u64,
+        self_trade_behavior: SelfTradeBehavior,
+        display_amount: u64,
+        linked_order_id: u64,
+        peg: Peg,
+        peg_offset: i64,
     ) -> Result<()> {
         msg!("Going to place_order; {:?}", ctx.program_id);
-        ctx.accounts.place_order(price, amount, side)?;
+        let bump = ctx.bumps.market;
+        let remaining_accounts = ctx.remaining_accounts;
+        ctx.accounts.place_order(
+            price,
+            amount,
+            side,
+            time_in_force,
+            post_only,
+            expires_at,
+            client_order_id,
+            self_trade_behavior,
+            display_amount,
+            linked_order_id,
+            peg,
+            peg_offset,
+            bump,
+            remaining_accounts,
+        )?;
+        Ok(())
+    }
+
+    /// Fills up to `worst_price` and never rests.
+    pub fn place_market_order<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PlaceOrder<'info>>,
+        amount: u64,
+        worst_price: u64,
+        side: Side,
+        self_trade_behavior: SelfTradeBehavior,
+    ) -> Result<()> {
+        let bump = ctx.bumps.market;
+        let remaining_accounts = ctx.remaining_accounts;
+        ctx.accounts.place_market_order(
+            amount,
+            worst_price,
+            side,
+            self_trade_behavior,
+            bump,
+            remaining_accounts,
+        )?;
+        Ok(())
+    }
+
+    /// Market buy that spends up to `quote_budget`.
+    pub fn place_quote_market_order<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PlaceOrder<'info>>,
+        quote_budget: u64,
+        worst_price: u64,
+        self_trade_behavior: SelfTradeBehavior,
+    ) -> Result<()> {
+        let bump = ctx.bumps.market;
+        let remaining_accounts = ctx.remaining_accounts;
+        ctx.accounts.place_quote_market_order(
+            quote_budget,
+            worst_price,
+            self_trade_behavior,
+            bump,
+            remaining_accounts,
+        )?;
+        Ok(())
+    }
+
+    /// Exact-in swap that fails if less than `min_amount_out` comes back.
+    pub fn swap<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PlaceOrder<'info>>,
+        amount_in: u64,
+        min_amount_out: u64,
+        direction: SwapDirection,
+    ) -> Result<()> {
+        let bump = ctx.bumps.market;
+        let remaining_accounts = ctx.remaining_accounts;
+        ctx.accounts.swap(
+            amount_in,
+            min_amount_out,
+            direction,
+            bump,
+            remaining_accounts,
+        )?;
+        Ok(())
+    }
+
+    /// Rests non-crossing orders with one escrow transfer per mint.
+    pub fn place_orders(ctx: Context<PlaceOrder>, orders: Vec<BatchOrder>) -> Result<()> {
+        ctx.accounts.place_orders(orders)?;
+        Ok(())
+    }
+
+    /// Rests a fixed-price order without matching it; a crossing one is left for the crank.
+    pub fn post_order(
+        ctx: Context<PostOrder>,
+        side: Side,
+        price: u64,
+        amount: u64,
+        expires_at: i64,
+    ) -> Result<()> {
+        ctx.accounts.post_order(side, price, amount, expires_at)?;
         Ok(())
     }
+
     pub fn cancel_order(ctx: Context<CancelOrder>, side: Side, order_id: u64) -> Result<()> {
         let bump = ctx.bumps.market;
         ctx.accounts.cancel_order(side, order_id, bump)?;
         Ok(())
     }
 
+    pub fn cancel_order_by_client_id(
+        ctx: Context<CancelOrder>,
+        client_order_id: u64,
+    ) -> Result<()> {
+        let bump = ctx.bumps.market;
+        ctx.accounts
+            .cancel_order_by_client_id(client_order_id, bump)?;
+        Ok(())
+    }
+
+    pub fn cancel_all_orders(ctx: Context<CancelOrder>, side: Option<Side>) -> Result<()> {
+        let bump = ctx.bumps.market;
+        ctx.accounts.cancel_all_orders(side, bump)?;
+        Ok(())
+    }
+
+    /// Moves only the escrow difference; a new price that crosses is left for the crank.
+    pub fn amend_order(
+        ctx: Context<AmendOrder>,
+        side: Side,
+        order_id: u64,
+        new_price: u64,
+        new_amount: u64,
+    ) -> Result<()> {
+        let bump = ctx.bumps.market;
+        ctx.accounts
+            .amend_order(side, order_id, new_price, new_amount, bump)?;
+        Ok(())
+    }
+
+    /// Escrowed now and fired by the first trade whose price reaches `trigger_price`.
+    pub fn place_stop_order(
+        ctx: Context<PlaceStopOrder>,
+        side: Side,
+        kind: StopKind,
+        trigger_price: u64,
+        price: u64,
+        amount: u64,
+        trailing: Option<TrailingOffset>,
+    ) -> Result<()> {
+        ctx.accounts
+            .place_stop_order(side, kind, trigger_price, price, amount, trailing)?;
+        Ok(())
+    }
+
+    pub fn cancel_stop_order(
+        ctx: Context<CancelStopOrder>,
+        side: Side,
+        order_id: u64,
+    ) -> Result<()> {
+        let bump = ctx.bumps.market;
+        ctx.accounts.cancel_stop_order(side, order_id, bump)?;
+        Ok(())
+    }
+
+    /// Remaining accounts are the ATAs of the order owners, stop order owners and cranker.
     pub fn match_order<'info>(
         ctx: Context<'_, '_, 'info, 'info, MatchOrders<'info>>,
     ) -> Result<()> {
@@ -43,4 +219,16 @@ pub mod orderbook_dex {
         ctx.accounts.match_orders(bump, remaining_accounts)?;
         Ok(())
     }
+
+    /// Remaining accounts are the owners' ATAs for the refunds.
+    pub fn sweep_expired_orders<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SweepExpiredOrders<'info>>,
+        side: Side,
+    ) -> Result<()> {
+        let bump = ctx.bumps.market;
+        let remaining_accounts = ctx.remaining_accounts;
+        ctx.accounts
+            .sweep_expired_orders(side, bump, remaining_accounts)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,350 @@
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::get_associated_token_address;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+
+use crate::errors::OrderBookError;
+use crate::states::{Fill, LimitOrder, Market, Side, Take};
+
+/// Quote units for `amount` base units at `price`: (amount * price) / 10^base_decimals
+pub fn quote_amount(amount: u64, price: u64, base_decimals: u8) -> Result<u64> {
+    let base_scale = 10u64.pow(base_decimals as u32);
+    let quote = amount
+        .checked_mul(price)
+        .ok_or(OrderBookError::ErrorInMultiply)?
+        .checked_div(base_scale)
+        .ok_or(OrderBookError::ErrorInMultiply)?;
+    Ok(quote)
+}
+
+/// Escrow held in the vaults for a resting `order` on `side`, hidden reserve included.
+pub fn escrow_of(side: &Side, order: &LimitOrder, base_decimals: u8) -> Result<u64> {
+    let amount = order
+        .amount
+        .checked_add(order.hidden_amount)
+        .ok_or(OrderBookError::ErrorInMultiply)?;
+    match side {
+        Side::Bid => quote_amount(amount, order.price, base_decimals),
+        Side::Ask => Ok(amount),
+    }
+}
+
+/// One market vault and what is needed to move tokens out of it as the market PDA, for
+/// instructions that touch the escrow of one side only.
+pub struct Vault<'a, 'info> {
+    pub market: &'a Account<'info, Market>,
+    pub mint: &'a InterfaceAccount<'info, Mint>,
+    pub vault: &'a InterfaceAccount<'info, TokenAccount>,
+    pub token_program: &'a Interface<'info, TokenInterface>,
+    pub signer_seeds: &'a [&'a [&'a [u8]]],
+}
+
+impl<'a, 'info> Vault<'a, 'info> {
+    pub fn pay(&self, to: AccountInfo<'info>, amount: u64) -> Result<()> {
+        let ctx_acc = TransferChecked {
+            from: self.vault.to_account_info(),
+            to,
+            mint: self.mint.to_account_info(),
+            authority: self.market.to_account_info(),
+        };
+
+        let ctx = CpiContext::new(self.token_program.to_account_info(), ctx_acc)
+            .with_signer(self.signer_seeds);
+        token_interface::transfer_checked(ctx, amount, self.mint.decimals)
+    }
+
+    /// Returns the escrow of a resting `order` that left the book without filling to its
+    /// owner's ATA of the vault's mint, looked up in `remaining_accounts`.
+    pub fn refund_order(
+        &self,
+        order: &LimitOrder,
+        escrow: u64,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let owner_ata = find_ata(remaining_accounts, order.owner, self.mint.key())?;
+        self.pay(owner_ata.to_account_info(), escrow)
+    }
+}
+
+/// The market vaults and what is needed to move tokens out of them as the market PDA.
+pub struct Settlement<'a, 'info> {
+    pub market: &'a Account<'info, Market>,
+    pub base_mint: &'a InterfaceAccount<'info, Mint>,
+    pub quote_mint: &'a InterfaceAccount<'info, Mint>,
+    pub base_mint_vault: &'a InterfaceAccount<'info, TokenAccount>,
+    pub quote_mint_vault: &'a InterfaceAccount<'info, TokenAccount>,
+    pub token_program: &'a Interface<'info, TokenInterface>,
+    pub signer_seeds: &'a [&'a [&'a [u8]]],
+}
+
+impl<'a, 'info> Settlement<'a, 'info> {
+    /// The vault holding the escrow of `side`: quote for bids, base for asks.
+    pub fn vault(&self, side: &Side) -> Vault<'a, 'info> {
+        let (mint, vault) = match side {
+            Side::Bid => (self.quote_mint, self.quote_mint_vault),
+            Side::Ask => (self.base_mint, self.base_mint_vault),
+        };
+        Vault {
+            market: self.market,
+            mint,
+            vault,
+            token_program: self.token_program,
+            signer_seeds: self.signer_seeds,
+        }
+    }
+
+    pub fn pay_base(&self, to: AccountInfo<'info>, amount: u64) -> Result<()> {
+        self.vault(&Side::Ask).pay(to, amount)
+    }
+
+    pub fn pay_quote(&self, to: AccountInfo<'info>, amount: u64) -> Result<()> {
+        self.vault(&Side::Bid).pay(to, amount)
+    }
+
+    /// Market fee the asker pays on a fill worth `quote_amount`.
+    pub fn fee_of(&self, quote_amount: u64) -> Result<u64> {
+        Ok(quote_amount
+            .checked_mul(self.market.fee_bps as u64)
+            .ok_or(OrderBookError::ErrorInMultiply)?
+            / 10000)
+    }
+
+    /// Escrow held in the vaults for a resting `order` on `side`, hidden reserve included.
+    pub fn escrow_of(&self, side: &Side, order: &LimitOrder) -> Result<u64> {
+        escrow_of(side, order, self.base_mint.decimals)
+    }
+
+    /// Returns the escrow of a resting `order` on `side` that left the book without filling
+    /// to its owner's ATA, looked up in `remaining_accounts`.
+    pub fn refund_order(
+        &self,
+        side: &Side,
+        order: &LimitOrder,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let escrow = self.escrow_of(side, order)?;
+        self.vault(side)
+            .refund_order(order, escrow, remaining_accounts)
+    }
+
+    /// Pays out one fill from the vaults. The bidder receives the base, the asker receives
+    /// the quote minus the market fee. 10% of the fee goes to the cranker when there is one,
+    /// the rest to the fee collector. Returns the gross quote amount of the fill.
+    pub fn settle_fill(
+        &self,
+        fill_amount: u64,
+        execution_price: u64,
+        bidder_base: AccountInfo<'info>,
+        asker_quote: AccountInfo<'info>,
+        fee_collector: AccountInfo<'info>,
+        cranker_quote: Option<AccountInfo<'info>>,
+    ) -> Result<u64> {
+        let quote_amount = quote_amount(fill_amount, execution_price, self.base_mint.decimals)?;
+
+        //Calculate the fee
+        let total_fee = self.fee_of(quote_amount)?;
+        let asker_fee = total_fee;
+
+        self.pay_quote(asker_quote, quote_amount - asker_fee)?;
+        self.pay_base(bidder_base, fill_amount)?;
+
+        let market_fee = match cranker_quote {
+            Some(cranker_quote) => {
+                let cranker_reward = total_fee / 10;
+                self.pay_quote(cranker_quote, cranker_reward)?;
+                total_fee - cranker_reward
+            }
+            None => total_fee,
+        };
+        self.pay_quote(fee_collector, market_fee)?;
+
+        Ok(quote_amount)
+    }
+
+    /// Returns to `bidder`, whose escrow is held at `bid_price`, what it did not need for
+    /// `fill_amount` filled at the lower `execution_price`. The bidder's quote ATA is looked
+    /// up in `remaining_accounts` only when there is something to refund.
+    pub fn refund_price_improvement(
+        &self,
+        bidder: Pubkey,
+        fill_amount: u64,
+        bid_price: u64,
+        execution_price: u64,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let escrowed = quote_amount(fill_amount, bid_price, self.base_mint.decimals)?;
+        let paid = quote_amount(fill_amount, execution_price, self.base_mint.decimals)?;
+        if escrowed <= paid {
+            return Ok(());
+        }
+
+        let bidder_quote = find_ata(remaining_accounts, bidder, self.quote_mint.key())?;
+        self.pay_quote(bidder_quote.to_account_info(), escrowed - paid)
+    }
+
+    /// Settles the fills of an incoming order on `side`. The taker's own accounts receive its
+    /// proceeds, each maker is paid into its ATA looked up in `remaining_accounts`.
+    /// Returns the total gross quote traded.
+    pub fn settle_taker_fills(
+        &self,
+        side: &Side,
+        fills: &[Fill],
+        taker_base: &Option<AccountInfo<'info>>,
+        taker_quote: &Option<AccountInfo<'info>>,
+        fee_collector: AccountInfo<'info>,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<u64> {
+        let mut quote_traded: u64 = 0;
+
+        for fill in fills {
+            let (bidder_base, asker_quote) = match side {
+                Side::Bid => (
+                    needed(taker_base)?,
+                    find_ata(remaining_accounts, fill.maker, self.quote_mint.key())?
+                        .to_account_info(),
+                ),
+                Side::Ask => (
+                    find_ata(remaining_accounts, fill.maker, self.base_mint.key())?
+                        .to_account_info(),
+                    needed(taker_quote)?,
+                ),
+            };
+
+            msg!(
+                "Filled {} at price={} against order_id={}",
+                fill.amount,
+                fill.price,
+                fill.maker_order_id
+            );
+
+            let quote = self.settle_fill(
+                fill.amount,
+                fill.price,
+                bidder_base,
+                asker_quote,
+                fee_collector.clone(),
+                None,
+            )?;
+            // Bid makers are pegged orders filled below the price their escrow is held at
+            if *side == Side::Ask {
+                self.refund_price_improvement(
+                    fill.maker,
+                    fill.amount,
+                    fill.maker_price,
+                    fill.price,
+                    remaining_accounts,
+                )?;
+            }
+            quote_traded = quote_traded
+                .checked_add(quote)
+                .ok_or(OrderBookError::ErrorInMultiply)?;
+        }
+
+        Ok(quote_traded)
+    }
+
+    /// Settles `take`, the result of an incoming order on `side` that escrowed `escrow` at
+    /// `limit_price`. Fills are paid out as in `settle_taker_fills`, own resting orders
+    /// cancelled by self-trade prevention are refunded to the taker, and so is whatever
+    /// escrow is not needed for the `resting` amount left in the book. Makers' orders
+    /// cancelled as the other leg of a one-cancels-other pair are refunded to their owners.
+    /// The taker's accounts are only needed for what is paid into them.
+    #[allow(clippy::too_many_arguments)]
+    pub fn settle_take(
+        &self,
+        side: &Side,
+        limit_price: u64,
+        escrow: u64,
+        take: &Take,
+        resting: u64,
+        taker_base: Option<AccountInfo<'info>>,
+        taker_quote: Option<AccountInfo<'info>>,
+        fee_collector: AccountInfo<'info>,
+        remaining_accounts: &'info [AccountInfo<'info>],
+    ) -> Result<()> {
+        let quote_traded = self.settle_taker_fills(
+            side,
+            &take.fills,
+            &taker_base,
+            &taker_quote,
+            fee_collector,
+            remaining_accounts,
+        )?;
+
+        // Own resting orders cancelled by self-trade prevention belong to the taker
+        let maker_side = match side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        let mut maker_refund: u64 = 0;
+        for cancelled in &take.self_trade_cancels {
+            msg!(
+                "Self-trade prevention cancelled {} of order_id={}",
+                { cancelled.amount },
+                { cancelled.order_id }
+            );
+            maker_refund = maker_refund
+                .checked_add(self.escrow_of(&maker_side, cancelled)?)
+                .ok_or(OrderBookError::ErrorInMultiply)?;
+        }
+        if maker_refund > 0 {
+            match maker_side {
+                Side::Bid => self.pay_quote(needed(&taker_quote)?, maker_refund)?,
+                Side::Ask => self.pay_base(needed(&taker_base)?, maker_refund)?,
+            }
+        }
+
+        for (linked_side, linked) in &take.linked_cancels {
+            msg!("One-cancels-other cancelled order_id={}", {
+                linked.order_id
+            });
+            self.refund_order(linked_side, linked, remaining_accounts)?;
+        }
+
+        match side {
+            Side::Bid => {
+                let resting_escrow = quote_amount(resting, limit_price, self.base_mint.decimals)?;
+                let refund = escrow
+                    .checked_sub(quote_traded)
+                    .and_then(|left| left.checked_sub(resting_escrow))
+                    .ok_or(OrderBookError::ErrorInMultiply)?;
+
+                if refund > 0 {
+                    self.pay_quote(needed(&taker_quote)?, refund)?;
+                }
+            }
+            Side::Ask => {
+                let filled: u64 = take.fills.iter().map(|fill| fill.amount).sum();
+                let refund = escrow - filled - resting;
+
+                if refund > 0 {
+                    self.pay_base(needed(&taker_base)?, refund)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `account`, which a payment into it cannot do without.
+fn needed<'info>(account: &Option<AccountInfo<'info>>) -> Result<AccountInfo<'info>> {
+    account
+        .clone()
+        .ok_or_else(|| error!(OrderBookError::AtaNotFound))
+}
+
+pub fn find_ata<'info>(
+    accounts: &'info [AccountInfo<'info>],
+    owner: Pubkey,
+    mint: Pubkey,
+) -> Result<InterfaceAccount<'info, TokenAccount>> {
+    let expected_ata = get_associated_token_address(&owner, &mint);
+
+    for account in accounts {
+        if account.key() == expected_ata {
+            return InterfaceAccount::try_from(account);
+        }
+    }
+
+    err!(OrderBookError::AtaNotFound)
+}
@@ -1,32 +1,1257 @@
+use std::cell::RefMut;
+use std::cmp::{max, min};
+use std::mem::size_of;
+
 use anchor_lang::prelude::*;
 
+use crate::book_side::{arrival_slot, BookSide, Slot, INDEXES};
+
 #[derive(AnchorDeserialize, AnchorSerialize, Clone, PartialEq, InitSpace)]
 pub enum Side {
     Bid,
     Ask,
 }
 
-#[derive(AnchorDeserialize, AnchorSerialize, Clone, InitSpace, Copy)]
+impl Side {
+    pub fn opposite(&self) -> Side {
+        match self {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        }
+    }
+
+    /// First seed of the `OrderBookSide` account holding this side of a market's book.
+    pub fn book_seed(&self) -> &'static [u8] {
+        match self {
+            Side::Bid => b"market_bids",
+            Side::Ask => b"market_asks",
+        }
+    }
+
+    /// Side order `order_id` was placed on, told by its parity, see
+    /// `OrderBookSide::next_order_id`.
+    pub fn of_order(order_id: u64) -> Side {
+        match order_id % 2 {
+            0 => Side::Bid,
+            _ => Side::Ask,
+        }
+    }
+}
+
+/// How long an order placed through `place_order` may live in the book.
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, PartialEq)]
+pub enum TimeInForce {
+    /// Rests until filled or cancelled.
+    GoodTillCancel,
+    /// Fills whatever crosses right away, the remainder is refunded instead of resting.
+    ImmediateOrCancel,
+    /// Fills in full right away or the whole instruction fails.
+    FillOrKill,
+}
+
+/// What `place_order` does with a post-only order that would cross the spread.
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, PartialEq)]
+pub enum PostOnly {
+    /// Not post-only, crossing orders take liquidity.
+    Disabled,
+    /// Fail the instruction instead of taking.
+    Reject,
+    /// Re-price to one tick behind the opposite best price.
+    Slide,
+}
+
+/// What happens when an order would match a resting order of the same owner. The setting
+/// of the incoming order decides; in a crank match the newer order counts as incoming.
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, PartialEq, InitSpace)]
+pub enum SelfTradeBehavior {
+    /// Cancel the rest of the incoming order, the resting order stays.
+    CancelTaker,
+    /// Cancel the resting order and keep matching.
+    CancelMaker,
+    /// Cancel the resting order and the rest of the incoming order.
+    CancelBoth,
+    /// Reduce both by the overlapping amount without trading.
+    DecrementAndCancel,
+}
+
+/// How an incoming order's fill is shared among the resting orders at one price.
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, PartialEq, InitSpace)]
+pub enum MatchingAlgorithm {
+    /// Price-time priority: the oldest order at a price fills first.
+    Fifo,
+    /// In proportion to the visible size of each order. With `top_order_priority` the oldest
+    /// order at the price is filled first and only the rest is shared.
+    ProRata { top_order_priority: bool },
+}
+
+/// What the price of a resting order follows. Pegged orders track the best fixed-price
+/// orders of the book, so they never follow each other.
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, PartialEq, InitSpace)]
+pub enum Peg {
+    /// Rests at its own price.
+    Fixed,
+    BestBid,
+    BestAsk,
+    /// Halfway between the best bid and the best ask.
+    Mid,
+}
+
+/// Which way a `swap` trades.
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, PartialEq)]
+pub enum SwapDirection {
+    /// Sell base for quote.
+    BaseToQuote,
+    /// Buy base with quote.
+    QuoteToBase,
+}
+
+/// One entry of a `place_orders` batch.
+#[derive(AnchorDeserialize, AnchorSerialize, Clone)]
+pub struct BatchOrder {
+    pub side: Side,
+    pub price: u64,
+    pub amount: u64,
+    pub client_order_id: u64,
+}
+
+/// Resting order as stored in the book. Packed, so no space goes to padding; copy a field out
+/// before taking a reference to it.
+#[zero_copy]
+#[repr(C, packed)]
 pub struct LimitOrder {
     pub owner: Pubkey,
     pub price: u64,
+    /// Visible amount, the only part that can match at a time.
     pub amount: u64,
     pub order_id: u64,
+    /// Trader-assigned id, 0 when not set, at most `MAX_CLIENT_ORDER_ID`.
+    pub client_order_id: u64,
+    /// Unix timestamp after which the order no longer matches, 0 for never.
+    pub expires_at: i64,
+    /// A `SelfTradeBehavior`, read through `self_trade_behavior()`.
+    pub self_trade_behavior: u8,
+    /// Iceberg peak size the visible amount refills to, 0 for a plain order.
+    pub display_amount: u64,
+    /// Iceberg reserve not yet shown in the book.
+    pub hidden_amount: u64,
+    /// Other leg of a one-cancels-other pair, 0 for none.
+    pub linked_order_id: u64,
+    /// For a pegged order `price` is its limit, the most it bids or the least it asks, and
+    /// what its escrow is held at. A `Peg`, read through `peg()`.
+    pub peg: u8,
+    /// Distance from the peg reference in price units.
+    pub peg_offset: i64,
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct OrderBook {
+impl LimitOrder {
+    /// Highest client order id a trader can assign. The owner tree of a book side keys orders
+    /// without one above it.
+    pub const MAX_CLIENT_ORDER_ID: u64 = u64::MAX >> 1;
+
+    pub fn self_trade_behavior(&self) -> SelfTradeBehavior {
+        match self.self_trade_behavior {
+            0 => SelfTradeBehavior::CancelTaker,
+            1 => SelfTradeBehavior::CancelMaker,
+            2 => SelfTradeBehavior::CancelBoth,
+            _ => SelfTradeBehavior::DecrementAndCancel,
+        }
+    }
+
+    pub fn peg(&self) -> Peg {
+        match self.peg {
+            0 => Peg::Fixed,
+            1 => Peg::BestBid,
+            2 => Peg::BestAsk,
+            _ => Peg::Mid,
+        }
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at != 0 && self.expires_at <= now
+    }
+
+    /// Visible plus hidden amount.
+    pub fn total_amount(&self) -> u64 {
+        self.amount + self.hidden_amount
+    }
+
+    /// Splits `total` into a visible part of at most `display_amount` and a hidden reserve.
+    pub fn set_total_amount(&mut self, total: u64) {
+        self.amount = match self.display_amount {
+            0 => total,
+            display_amount => min(display_amount, total),
+        };
+        self.hidden_amount = total - self.amount;
+    }
+}
+
+/// One side of a market's order book, the bids or the asks. Each side has its own account so
+/// that instructions writing only one side, such as `post_order`, `amend_order`, a cancel or
+/// a sweep, leave the other free for transactions running alongside. The header is followed
+/// by `capacity` slots that `BookSide` reads the orders from, so the account is sized by
+/// `space`.
+#[account(zero_copy)]
+#[repr(C, packed)]
+pub struct OrderBookSide {
     pub market: Pubkey,
+    /// Id the next order on this side gets. Bids take even ids and asks odd ones, so ids are
+    /// unique across the market without the sides sharing a counter. Both start above 0,
+    /// which stands for no order in `linked_order_id`.
     pub next_order_id: u64,
-    #[max_len(50)]
-    pub bids: Vec<LimitOrder>,
-    #[max_len(50)]
-    pub asks: Vec<LimitOrder>,
+    /// Orders the side has room for.
+    pub capacity: u32,
+    pub len: u32,
+    /// Pegged orders on the side, so sides without any skip repricing.
+    pub pegged: u32,
+    /// Root of each tree, see `book_side::Index`.
+    pub roots: [u32; INDEXES],
+    /// Lowest arrival the next order on the side can get, see `BookSide::insert`.
+    pub next_sequence: u64,
+    /// Best fixed-price bid and ask the pegged orders on this side were last priced from, 0
+    /// for none. An instruction that writes only this side updates its own best price here
+    /// and leaves the other as it was; the next one loading both sides catches up.
+    pub priced_from_bid: u64,
+    pub priced_from_ask: u64,
+}
+
+// A side or the stop orders of a market are created through a CPI, which caps a new account at
+// 10 KiB; an account can also grow by no more than that in one instruction
+const _: () = assert!(8 + OrderBookSide::space(OrderBookSide::MAX_INITIAL_CAPACITY) <= 10240);
+const _: () = assert!(8 + TriggerOrders::space(OrderBookSide::MAX_INITIAL_CAPACITY) <= 10240);
+
+impl OrderBookSide {
+    /// Most orders a side can be created with.
+    pub const MAX_INITIAL_CAPACITY: u32 = 64;
+
+    /// Bytes the account takes after its discriminator with room for `capacity` orders.
+    pub const fn space(capacity: u32) -> usize {
+        size_of::<OrderBookSide>() + capacity as usize * size_of::<Slot>()
+    }
+
+    /// Hands out `next_order_id`.
+    pub fn take_order_id(&mut self) -> u64 {
+        let order_id = self.next_order_id;
+        self.next_order_id = order_id.checked_add(2).unwrap();
+        order_id
+    }
+}
+
+/// Both sides of a market's book, read and written in place.
+pub struct Book<'a> {
+    /// Highest price first, then in order of arrival at that price.
+    pub bids: BookSide<'a>,
+    /// Lowest price first, then in order of arrival at that price.
+    pub asks: BookSide<'a>,
+}
+
+/// One execution of an incoming order against a resting maker order.
+#[derive(Clone, Copy)]
+pub struct Fill {
+    pub maker: Pubkey,
+    pub maker_order_id: u64,
+    pub price: u64,
+    /// Price the maker's escrow is held at, above `price` for a pegged bid below its limit.
+    pub maker_price: u64,
+    pub amount: u64,
+}
+
+/// What the amount an incoming order takes is counted in.
+#[derive(Clone, Copy)]
+enum TakeSize {
+    /// Base units to fill.
+    Base,
+    /// Quote units to spend.
+    Quote { base_decimals: u8 },
+}
+
+impl TakeSize {
+    /// Most base units `remaining` covers at `price`.
+    fn max_fill(&self, remaining: u64, price: u64) -> u64 {
+        match self {
+            TakeSize::Base => remaining,
+            TakeSize::Quote { base_decimals } => {
+                let base_scale = 10u128.pow(*base_decimals as u32);
+                min(
+                    remaining as u128 * base_scale / price as u128,
+                    u64::MAX as u128,
+                ) as u64
+            }
+        }
+    }
+
+    /// What filling `amount` base units at `price` uses up of the remaining size.
+    fn cost(&self, amount: u64, price: u64) -> u64 {
+        match self {
+            TakeSize::Base => amount,
+            TakeSize::Quote { base_decimals } => {
+                let base_scale = 10u128.pow(*base_decimals as u32);
+                (amount as u128 * price as u128 / base_scale) as u64
+            }
+        }
+    }
+}
+
+/// Best live fixed-price bid and ask, what pegged orders are priced from.
+#[derive(Clone, Copy, PartialEq)]
+pub struct PegReferences {
+    pub best_bid: Option<u64>,
+    pub best_ask: Option<u64>,
+}
+
+impl PegReferences {
+    /// What the pegged orders on the side of `header` were last priced from.
+    fn last_of(header: &OrderBookSide) -> PegReferences {
+        let price = |price: u64| (price != 0).then_some(price);
+        PegReferences {
+            best_bid: price(header.priced_from_bid),
+            best_ask: price(header.priced_from_ask),
+        }
+    }
+
+    /// Price `order` on `side` trades at: its own price when fixed, otherwise its reference
+    /// plus offset, held to its limit. None while the reference is missing.
+    pub fn price_of(&self, side: &Side, order: &LimitOrder) -> Option<u64> {
+        let reference = match order.peg() {
+            Peg::Fixed => return Some(order.price),
+            Peg::BestBid => self.best_bid?,
+            Peg::BestAsk => self.best_ask?,
+            Peg::Mid => ((self.best_bid? as u128 + self.best_ask? as u128) / 2) as u64,
+        };
+        let pegged = max(reference.checked_add_signed(order.peg_offset)?, 1);
+
+        Some(match side {
+            Side::Bid => min(pegged, order.price),
+            Side::Ask => max(pegged, order.price),
+        })
+    }
+
+    /// `price_of`, with orders that have no price ranked behind every other order.
+    fn rank_price(&self, side: &Side, order: &LimitOrder) -> u64 {
+        self.price_of(side, order).unwrap_or(match side {
+            Side::Bid => 0,
+            Side::Ask => u64::MAX,
+        })
+    }
 }
 
-impl OrderBook {
-    pub const MAX_ORDERS: usize = 50;
+/// Resting orders an incoming order can fill against, see `Book::reach`.
+pub struct Reach {
+    /// With the price each trades at.
+    pub makers: Vec<(LimitOrder, u64)>,
+    pub fills_in_full: bool,
+    /// The reach ends at an order of the taker's own.
+    pub self_trade: bool,
+}
+
+/// Outcome of matching an incoming order against the book.
+pub struct Take {
+    pub fills: Vec<Fill>,
+    /// The taker's own resting orders, or parts of them, removed by self-trade prevention.
+    pub self_trade_cancels: Vec<LimitOrder>,
+    /// Other legs of one-cancels-other pairs whose maker filled or was cancelled by self-trade
+    /// prevention, removed from the book.
+    pub linked_cancels: Vec<(Side, LimitOrder)>,
+    /// Unfilled amount of the incoming order that was not cancelled.
+    pub remaining: u64,
+}
+
+impl<'a> Book<'a> {
+    /// Borrows both sides for as long as the book is held, and reprices the pegged orders a
+    /// change to one side alone left behind.
+    pub fn load(
+        bids: &'a AccountLoader<'_, OrderBookSide>,
+        asks: &'a AccountLoader<'_, OrderBookSide>,
+    ) -> Result<Book<'a>> {
+        let mut book = Book {
+            bids: BookSide::load(bids)?,
+            asks: BookSide::load(asks)?,
+        };
+        book.reprice_pegged();
+        Ok(book)
+    }
+
+    /// Hands out the id for the next order on `side`.
+    pub fn next_order_id(&mut self, side: &Side) -> u64 {
+        self.book_mut(side).header.take_order_id()
+    }
+
+    /// Best live price on `side`: the highest bid or the lowest ask, pegged orders included.
+    pub fn best_price(&self, side: &Side, now: i64) -> Option<u64> {
+        let references = self.peg_references();
+        self.book(side)
+            .iter()
+            .filter(|order| !order.is_expired(now))
+            .find_map(|order| references.price_of(side, order))
+    }
+
+    /// Current prices pegged orders follow: the best fixed-price order on each side. Expired
+    /// orders count until they are swept, so the order of the book never depends on the clock.
+    pub fn peg_references(&self) -> PegReferences {
+        PegReferences {
+            best_bid: self.bids.best_fixed().map(|bid| bid.price),
+            best_ask: self.asks.best_fixed().map(|ask| ask.price),
+        }
+    }
+
+    /// Whether an order at `price` on `side` would match the opposite best price.
+    pub fn crosses(&self, side: &Side, price: u64, now: i64) -> bool {
+        match side {
+            Side::Bid => self
+                .best_price(&Side::Ask, now)
+                .is_some_and(|best_ask| price >= best_ask),
+            Side::Ask => self
+                .best_price(&Side::Bid, now)
+                .is_some_and(|best_bid| price <= best_bid),
+        }
+    }
+
+    /// Live amount on the side opposite `side` at `limit_price` or better, not counting
+    /// `taker`'s own orders.
+    pub fn liquidity_at(&self, side: &Side, limit_price: u64, now: i64, taker: &Pubkey) -> u64 {
+        let references = self.peg_references();
+        let maker_side = side.opposite();
+        self.book(&maker_side)
+            .iter()
+            .filter(|order| !order.is_expired(now) && order.owner != *taker)
+            .take_while(|order| {
+                references
+                    .price_of(&maker_side, order)
+                    .is_some_and(|price| match side {
+                        Side::Bid => price <= limit_price,
+                        Side::Ask => price >= limit_price,
+                    })
+            })
+            .map(|order| order.total_amount())
+            .sum()
+    }
+
+    /// Orders on the side opposite `side` that a `take` of `amount` at `limit_price` by `taker`
+    /// can fill against or cancel at an own order, without changing the book. The price level
+    /// the amount runs out at is included whole: pro-rata shares and iceberg refills spread a
+    /// fill across it.
+    pub fn reach(
+        &self,
+        side: &Side,
+        limit_price: u64,
+        amount: u64,
+        now: i64,
+        taker: &Pubkey,
+    ) -> Reach {
+        let references = self.peg_references();
+        let maker_side = side.opposite();
+        let mut makers = Vec::new();
+        let mut reached: u64 = 0;
+        let mut last_price = None;
+
+        for order in self.book(&maker_side).iter() {
+            if order.is_expired(now) {
+                continue;
+            }
+            let Some(price) = references.price_of(&maker_side, order) else {
+                break;
+            };
+            let crosses = match side {
+                Side::Bid => price <= limit_price,
+                Side::Ask => price >= limit_price,
+            };
+            if !crosses || (reached >= amount && last_price != Some(price)) {
+                break;
+            }
+            if order.owner == *taker {
+                return Reach {
+                    makers,
+                    fills_in_full: false,
+                    self_trade: true,
+                };
+            }
+            reached = reached.saturating_add(order.total_amount());
+            last_price = Some(price);
+            makers.push((*order, price));
+        }
+
+        Reach {
+            makers,
+            fills_in_full: reached >= amount,
+            self_trade: false,
+        }
+    }
+
+    /// Fills up to `amount` against the side opposite `side`, best price first, for as long
+    /// as the resting price is no worse than `limit_price`. Makers execute at their own price
+    /// and are removed from the book once exhausted, icebergs refill from their reserve
+    /// instead. Expired makers are skipped and left for the crank or `sweep_expired_orders`
+    /// to refund. Makers owned by `taker` are handled according to `self_trade_behavior`
+    /// instead of filling. A maker that fills cancels the other leg of its one-cancels-other
+    /// pair.
+    #[allow(clippy::too_many_arguments)]
+    pub fn take(
+        &mut self,
+        side: &Side,
+        limit_price: u64,
+        amount: u64,
+        now: i64,
+        taker: &Pubkey,
+        self_trade_behavior: SelfTradeBehavior,
+        matching: MatchingAlgorithm,
+    ) -> Take {
+        self.take_sized(
+            side,
+            limit_price,
+            amount,
+            TakeSize::Base,
+            now,
+            taker,
+            self_trade_behavior,
+            matching,
+        )
+    }
+
+    /// Buys against the asks like `take`, but spends up to `quote_budget` instead of filling a
+    /// base amount. `Take::remaining` is the unspent budget, including dust too small to buy
+    /// one more base unit.
+    #[allow(clippy::too_many_arguments)]
+    pub fn take_quote(
+        &mut self,
+        limit_price: u64,
+        quote_budget: u64,
+        base_decimals: u8,
+        now: i64,
+        taker: &Pubkey,
+        self_trade_behavior: SelfTradeBehavior,
+        matching: MatchingAlgorithm,
+    ) -> Take {
+        self.take_sized(
+            &Side::Bid,
+            limit_price,
+            quote_budget,
+            TakeSize::Quote { base_decimals },
+            now,
+            taker,
+            self_trade_behavior,
+            matching,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn take_sized(
+        &mut self,
+        side: &Side,
+        limit_price: u64,
+        amount: u64,
+        size: TakeSize,
+        now: i64,
+        taker: &Pubkey,
+        self_trade_behavior: SelfTradeBehavior,
+        matching: MatchingAlgorithm,
+    ) -> Take {
+        let references = self.peg_references();
+        let maker_side = side.opposite();
+        let book = match maker_side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+
+        let mut fills = Vec::new();
+        let mut self_trade_cancels = Vec::new();
+        // Linked makers filled or cancelled, whose other legs go once the loop is done
+        let mut broken_links = Vec::new();
+        let mut remaining = amount;
+        // Key of the next maker to look at; skipped makers move it past themselves
+        let mut from = 0;
+
+        while remaining > 0 {
+            let Some((key, maker)) = book.first_from(from) else {
+                break;
+            };
+            let maker_id = maker.order_id;
+            if maker.is_expired(now) {
+                from = key + 1;
+                continue;
+            }
+
+            // Pegged orders without a reference are ranked last and never match
+            let Some(maker_price) = references.price_of(&maker_side, &maker) else {
+                break;
+            };
+            let crosses = match side {
+                Side::Bid => maker_price <= limit_price,
+                Side::Ask => maker_price >= limit_price,
+            };
+            if !crosses {
+                break;
+            }
+
+            if maker.owner == *taker {
+                match self_trade_behavior {
+                    SelfTradeBehavior::CancelTaker => {
+                        remaining = 0;
+                    }
+                    SelfTradeBehavior::CancelMaker => {
+                        self_trade_cancels.extend(book.remove(maker_id));
+                        broken_links.push(maker);
+                    }
+                    SelfTradeBehavior::CancelBoth => {
+                        self_trade_cancels.extend(book.remove(maker_id));
+                        broken_links.push(maker);
+                        remaining = 0;
+                    }
+                    SelfTradeBehavior::DecrementAndCancel => {
+                        let overlap = min(size.max_fill(remaining, maker_price), maker.amount);
+                        if overlap == 0 {
+                            break;
+                        }
+                        self_trade_cancels.push(LimitOrder {
+                            amount: overlap,
+                            hidden_amount: 0,
+                            ..maker
+                        });
+
+                        let decremented = book.get_mut(maker_id).unwrap();
+                        decremented.amount -= overlap;
+                        decremented.linked_order_id = 0;
+                        broken_links.push(maker);
+                        remaining -= size.cost(overlap, maker_price);
+
+                        if maker.amount == overlap {
+                            remove_or_refill(book, &maker_side, maker_id, &references);
+                        }
+                    }
+                }
+                continue;
+            }
+
+            if let MatchingAlgorithm::ProRata { top_order_priority } = matching {
+                // The taker's own orders come up for self-trade prevention once the others at
+                // this price are filled
+                let level = level_from(book, &maker_side, key, maker_price, &references, now, taker);
+                let amounts: Vec<u64> = level.iter().map(|order| order.amount).collect();
+                let quantity = min(size.max_fill(remaining, maker_price), amounts.iter().sum());
+                if quantity == 0 {
+                    break;
+                }
+
+                let allocations = pro_rata(&amounts, quantity, top_order_priority);
+                for (order, &fill_amount) in level.iter().zip(&allocations) {
+                    if fill_amount == 0 {
+                        continue;
+                    }
+                    fills.push(Fill {
+                        maker: order.owner,
+                        maker_order_id: order.order_id,
+                        price: maker_price,
+                        maker_price: order.price,
+                        amount: fill_amount,
+                    });
+
+                    let maker = book.get_mut(order.order_id).unwrap();
+                    if maker.linked_order_id != 0 {
+                        broken_links.push(*maker);
+                        maker.linked_order_id = 0;
+                    }
+
+                    maker.amount -= fill_amount;
+                    remaining -= size.cost(fill_amount, maker_price);
+                    if maker.amount == 0 {
+                        remove_or_refill(book, &maker_side, order.order_id, &references);
+                    }
+                }
+                continue;
+            }
+
+            let fill_amount = min(size.max_fill(remaining, maker_price), maker.amount);
+            if fill_amount == 0 {
+                break;
+            }
+            fills.push(Fill {
+                maker: maker.owner,
+                maker_order_id: maker_id,
+                price: maker_price,
+                maker_price: maker.price,
+                amount: fill_amount,
+            });
+
+            let maker = book.get_mut(maker_id).unwrap();
+            if maker.linked_order_id != 0 {
+                broken_links.push(*maker);
+                maker.linked_order_id = 0;
+            }
+
+            maker.amount -= fill_amount;
+            remaining -= size.cost(fill_amount, maker_price);
+
+            if maker.amount == 0 {
+                remove_or_refill(book, &maker_side, maker_id, &references);
+            }
+        }
+
+        let linked_cancels = broken_links
+            .iter()
+            .filter_map(|maker| self.remove_linked(maker))
+            .collect();
+        self.reprice_pegged();
+
+        Take {
+            fills,
+            self_trade_cancels,
+            linked_cancels,
+            remaining,
+        }
+    }
+
+    /// Side of whichever of the best bid and best ask took its place later, which counts as
+    /// the taker when the two cross.
+    pub fn top_taker_side(&self) -> Side {
+        let (bid_key, _) = self.bids.first_from(0).unwrap();
+        let (ask_key, _) = self.asks.first_from(0).unwrap();
+        // Arrivals are counted per side within a slot, so two orders placed in the same slot
+        // have no order between them; the ask counts as newer on a tie
+        if arrival_slot(bid_key) > arrival_slot(ask_key) {
+            Side::Bid
+        } else {
+            Side::Ask
+        }
+    }
+
+    /// Shares `amount` of the best order on `taker_side` across the best price level of the
+    /// other side, as `take` does on a pro-rata market. Returns each maker with its share,
+    /// leaving out those without one.
+    pub fn share_top_level(
+        &self,
+        taker_side: &Side,
+        amount: u64,
+        top_order_priority: bool,
+        now: i64,
+        taker: &Pubkey,
+    ) -> Vec<(LimitOrder, u64)> {
+        let references = self.peg_references();
+        let maker_side = taker_side.opposite();
+        let book = self.book(&maker_side);
+        let Some((key, best)) = book.first_from(0) else {
+            return Vec::new();
+        };
+        let Some(price) = references.price_of(&maker_side, &best) else {
+            return Vec::new();
+        };
+
+        let level = level_from(book, &maker_side, key, price, &references, now, taker);
+        let amounts: Vec<u64> = level.iter().map(|order| order.amount).collect();
+        let quantity = min(amount, amounts.iter().sum());
+        level
+            .into_iter()
+            .zip(pro_rata(&amounts, quantity, top_order_priority))
+            .filter(|(_, share)| *share > 0)
+            .collect()
+    }
+
+    /// Applies self-trade prevention to a crossing best bid and best ask of the same owner.
+    /// The one that took its place later counts as the taker and its setting decides. Returns
+    /// what was cancelled on each side, with the other legs of linked orders among it, so its
+    /// escrow can be refunded.
+    pub fn prevent_top_self_trade(&mut self) -> Vec<(Side, LimitOrder)> {
+        let (_, bid) = self.bids.first_from(0).unwrap();
+        let (_, ask) = self.asks.first_from(0).unwrap();
+        let bid_is_taker = self.top_taker_side() == Side::Bid;
+        let behavior = if bid_is_taker {
+            bid.self_trade_behavior()
+        } else {
+            ask.self_trade_behavior()
+        };
+
+        let (cancel_bid, cancel_ask) = match behavior {
+            SelfTradeBehavior::CancelTaker => (bid_is_taker, !bid_is_taker),
+            SelfTradeBehavior::CancelMaker => (!bid_is_taker, bid_is_taker),
+            SelfTradeBehavior::CancelBoth => (true, true),
+            SelfTradeBehavior::DecrementAndCancel => {
+                let overlap = min(bid.amount, ask.amount);
+                let references = self.peg_references();
+                let mut cancelled = Vec::new();
+
+                for (side, order) in [(Side::Bid, bid), (Side::Ask, ask)] {
+                    let book = self.book_mut(&side);
+                    let decremented = book.get_mut(order.order_id).unwrap();
+                    decremented.amount -= overlap;
+                    decremented.linked_order_id = 0;
+                    if order.amount == overlap {
+                        remove_or_refill(book, &side, order.order_id, &references);
+                    }
+                    cancelled.push((
+                        side,
+                        LimitOrder {
+                            amount: overlap,
+                            hidden_amount: 0,
+                            ..order
+                        },
+                    ));
+                }
+                cancelled.extend(self.remove_linked(&bid));
+                cancelled.extend(self.remove_linked(&ask));
+                self.reprice_pegged();
+
+                return cancelled;
+            }
+        };
+
+        let mut cancelled = Vec::new();
+        if cancel_bid {
+            cancelled.extend(self.bids.remove(bid.order_id).map(|bid| (Side::Bid, bid)));
+        }
+        if cancel_ask {
+            cancelled.extend(self.asks.remove(ask.order_id).map(|ask| (Side::Ask, ask)));
+        }
+        let linked: Vec<_> = cancelled
+            .iter()
+            .filter_map(|(_, order)| self.remove_linked(order))
+            .collect();
+        cancelled.extend(linked);
+        self.reprice_pegged();
+
+        cancelled
+    }
+
+    /// Takes the exhausted order `order_id` on `side` off the book, or refills it if it is an
+    /// iceberg with reserve left.
+    pub fn remove_or_refill(&mut self, side: &Side, order_id: u64) {
+        let references = self.peg_references();
+        remove_or_refill(self.book_mut(side), side, order_id, &references);
+        self.reprice_pegged();
+    }
+
+    /// Adds `order` to `side` behind every order at its price or better.
+    pub fn insert_order(&mut self, side: &Side, order: LimitOrder) {
+        let references = self.peg_references();
+        insert_by_price(self.book_mut(side), side, order, &references);
+        self.reprice_pegged();
+    }
+
+    /// Moves pegged orders to where their price puts them now. Runs after every change that
+    /// can move the best fixed-price bid or ask, see `reprice_side`.
+    fn reprice_pegged(&mut self) {
+        let references = self.peg_references();
+        for side in [Side::Bid, Side::Ask] {
+            reprice_side(self.book_mut(&side), &side, &references);
+        }
+    }
+
+    pub fn book(&self, side: &Side) -> &BookSide<'a> {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    fn book_mut(&mut self, side: &Side) -> &mut BookSide<'a> {
+        match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        }
+    }
+
+    /// Removes `owner`'s order `order_id` from `side`.
+    pub fn remove_order(
+        &mut self,
+        side: &Side,
+        owner: &Pubkey,
+        order_id: u64,
+    ) -> Option<LimitOrder> {
+        let book = match side {
+            Side::Bid => &mut self.bids,
+            Side::Ask => &mut self.asks,
+        };
+
+        if book.get(order_id)?.owner != *owner {
+            return None;
+        }
+        let order = book.remove(order_id)?;
+        self.reprice_pegged();
+        Some(order)
+    }
+
+    /// `owner`'s order `order_id` on either side.
+    pub fn order_mut(&mut self, owner: &Pubkey, order_id: u64) -> Option<&mut LimitOrder> {
+        let book = match self.bids.get(order_id) {
+            Some(_) => &mut self.bids,
+            None => &mut self.asks,
+        };
+        book.get_mut(order_id).filter(|order| order.owner == *owner)
+    }
+
+    /// Removes the other leg of `order`'s one-cancels-other pair, if it is still in the book.
+    pub fn remove_linked(&mut self, order: &LimitOrder) -> Option<(Side, LimitOrder)> {
+        if order.linked_order_id == 0 {
+            return None;
+        }
+
+        [Side::Bid, Side::Ask].into_iter().find_map(|side| {
+            self.remove_order(&side, &order.owner, order.linked_order_id)
+                .map(|linked| (side, linked))
+        })
+    }
+
+    /// Finds the order holding `owner`'s `client_order_id` on either side. Like
+    /// `BookSide::by_client_id` it can belong to another owner, which still keeps the id from
+    /// being placed again; cancels check the owner.
+    pub fn find_by_client_id(&self, owner: &Pubkey, client_order_id: u64) -> Option<(Side, u64)> {
+        if let Some(bid) = self.bids.by_client_id(owner, client_order_id) {
+            return Some((Side::Bid, bid.order_id));
+        }
+        self.asks
+            .by_client_id(owner, client_order_id)
+            .map(|ask| (Side::Ask, ask.order_id))
+    }
+}
+
+/// One side of a market's book on its own. Pegged orders on it are priced from its own best
+/// fixed price and the other side's as it was when last seen.
+pub struct SideBook<'a> {
+    pub side: Side,
+    pub book: BookSide<'a>,
+}
+
+impl<'a> SideBook<'a> {
+    pub fn load(side: Side, loader: &'a AccountLoader<'_, OrderBookSide>) -> Result<Self> {
+        Ok(SideBook {
+            side,
+            book: BookSide::load(loader)?,
+        })
+    }
+
+    /// Hands out the id for the next order on the side.
+    pub fn next_order_id(&mut self) -> u64 {
+        self.book.header.take_order_id()
+    }
+
+    /// What the side's pegged orders follow as far as it can tell.
+    fn peg_references(&self) -> PegReferences {
+        let own = self.book.best_fixed().map(|order| order.price);
+        let last = PegReferences::last_of(&self.book.header);
+        match self.side {
+            Side::Bid => PegReferences {
+                best_bid: own,
+                ..last
+            },
+            Side::Ask => PegReferences {
+                best_ask: own,
+                ..last
+            },
+        }
+    }
+
+    fn reprice_pegged(&mut self) {
+        let references = self.peg_references();
+        reprice_side(&mut self.book, &self.side, &references);
+    }
+
+    /// Adds `order` behind every order at its price or better.
+    pub fn insert_order(&mut self, order: LimitOrder) {
+        let references = self.peg_references();
+        insert_by_price(&mut self.book, &self.side, order, &references);
+        self.reprice_pegged();
+    }
+
+    /// Removes `owner`'s order `order_id`.
+    pub fn remove_order(&mut self, owner: &Pubkey, order_id: u64) -> Option<LimitOrder> {
+        if self.book.get(order_id)?.owner != *owner {
+            return None;
+        }
+        let order = self.book.remove(order_id)?;
+        self.reprice_pegged();
+        Some(order)
+    }
+
+    /// Re-prices and/or re-sizes `owner`'s order `order_id`, `amount` being the new total
+    /// size, and returns the order as it was and as it is now. A pure size reduction keeps
+    /// time priority, anything else moves the order behind every other order at its new
+    /// price.
+    pub fn amend_order(
+        &mut self,
+        owner: &Pubkey,
+        order_id: u64,
+        price: u64,
+        amount: u64,
+    ) -> Option<(LimitOrder, LimitOrder)> {
+        let previous = *self
+            .book
+            .get(order_id)
+            .filter(|order| order.owner == *owner)?;
+
+        if price == previous.price && amount <= previous.total_amount() {
+            // Shrink the reserve first so the visible part keeps its place
+            let amended = self.book.get_mut(order_id)?;
+            amended.amount = min(amended.amount, amount);
+            amended.hidden_amount = amount - amended.amount;
+            Some((previous, *amended))
+        } else {
+            let mut amended = self.book.remove(order_id)?;
+            amended.price = price;
+            amended.set_total_amount(amount);
+            self.insert_order(amended);
+            Some((previous, amended))
+        }
+    }
+
+    /// Removes every order that has expired by `now`, with the other leg of a one-cancels-other
+    /// pair on this side, and returns them. An expired leg linked to the other side stays for
+    /// the crank, which holds both.
+    pub fn remove_expired(&mut self, now: i64) -> Vec<LimitOrder> {
+        let side = self.side.clone();
+        let mut removed = self.book.drain_where(|order| {
+            order.is_expired(now)
+                && (order.linked_order_id == 0 || Side::of_order(order.linked_order_id) == side)
+        });
+        let linked: Vec<LimitOrder> = removed
+            .iter()
+            .filter(|order| order.linked_order_id != 0)
+            .filter_map(|order| self.remove_order(&order.owner, order.linked_order_id))
+            .collect();
+        removed.extend(linked);
+        self.reprice_pegged();
+        removed
+    }
+
+    /// Removes every order owned by `owner` and returns them.
+    pub fn remove_owned(&mut self, owner: &Pubkey) -> Vec<LimitOrder> {
+        let removed = self
+            .book
+            .owned_by(owner)
+            .into_iter()
+            .filter_map(|order_id| self.book.remove(order_id))
+            .collect();
+        self.reprice_pegged();
+        removed
+    }
+}
+
+/// What a stop order turns into once triggered.
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, PartialEq)]
+pub enum StopKind {
+    /// Fills whatever crosses up to its price, the remainder is refunded.
+    StopLoss,
+    /// Fills whatever crosses and rests the remainder as a limit order at its price.
+    StopLimit,
+}
+
+/// Distance a trailing stop keeps its trigger from the best traded price since placement.
+#[derive(AnchorDeserialize, AnchorSerialize, Clone, Copy, PartialEq)]
+pub enum TrailingOffset {
+    Absolute { offset: u64 },
+    Bps { bps: u16 },
+}
+
+impl TrailingOffset {
+    /// The offset in price units at `price`.
+    pub fn of(&self, price: u64) -> u64 {
+        match self {
+            TrailingOffset::Absolute { offset } => *offset,
+            TrailingOffset::Bps { bps } => (price as u128 * *bps as u128 / 10000) as u64,
+        }
+    }
+}
+
+/// An order held back in `TriggerOrders` until the last traded price reaches its trigger.
+/// Packed like `LimitOrder`.
+#[zero_copy]
+#[repr(C, packed)]
+pub struct StopOrder {
+    pub owner: Pubkey,
+    /// A `StopKind`, read through `kind()`.
+    pub kind: u8,
+    pub trigger_price: u64,
+    /// Limit price, for a stop-loss the worst price it fills at. Stays put while the trigger
+    /// of a trailing stop moves.
+    pub price: u64,
+    pub amount: u64,
+    /// Taken from the book side the order ends up in, so its parity tells the side.
+    pub order_id: u64,
+    /// Set for a trailing stop, whose trigger follows the traded price: 0 for none, 1 for an
+    /// absolute offset and 2 for one in bps. Read through `trailing()`.
+    pub trailing: u8,
+    pub trailing_offset: u64,
+}
+
+impl StopOrder {
+    pub fn kind(&self) -> StopKind {
+        match self.kind {
+            0 => StopKind::StopLoss,
+            _ => StopKind::StopLimit,
+        }
+    }
+
+    pub fn side(&self) -> Side {
+        Side::of_order(self.order_id)
+    }
+
+    pub fn trailing(&self) -> Option<TrailingOffset> {
+        match self.trailing {
+            0 => None,
+            1 => Some(TrailingOffset::Absolute {
+                offset: self.trailing_offset,
+            }),
+            _ => Some(TrailingOffset::Bps {
+                bps: self.trailing_offset as u16,
+            }),
+        }
+    }
+
+    pub fn set_trailing(&mut self, trailing: Option<TrailingOffset>) {
+        (self.trailing, self.trailing_offset) = match trailing {
+            None => (0, 0),
+            Some(TrailingOffset::Absolute { offset }) => (1, offset),
+            Some(TrailingOffset::Bps { bps }) => (2, bps as u64),
+        };
+    }
+
+    /// The order as it enters the book once triggered.
+    pub fn to_limit_order(&self) -> LimitOrder {
+        LimitOrder {
+            owner: self.owner,
+            price: self.price,
+            amount: self.amount,
+            order_id: self.order_id,
+            client_order_id: 0,
+            expires_at: 0,
+            self_trade_behavior: SelfTradeBehavior::CancelTaker as u8,
+            display_amount: 0,
+            hidden_amount: 0,
+            linked_order_id: 0,
+            peg: Peg::Fixed as u8,
+            peg_offset: 0,
+        }
+    }
+}
+
+/// Stop orders of a market waiting for their trigger, with their escrow already in the vaults.
+/// The header is followed by `capacity` stop orders that `StopBook` reads, so the account is
+/// sized by `space` and grows with the book.
+#[account(zero_copy)]
+#[repr(C, packed)]
+pub struct TriggerOrders {
+    pub market: Pubkey,
+    /// Execution price of the latest fill, taken or cranked, 0 until the first one.
+    pub last_traded_price: u64,
+    /// Stop orders, buys and sells together, the account has room for.
+    pub capacity: u32,
+    pub len: u32,
+}
+
+impl TriggerOrders {
+    /// Bytes the account takes after its discriminator with room for `capacity` stop orders.
+    pub const fn space(capacity: u32) -> usize {
+        size_of::<TriggerOrders>() + capacity as usize * size_of::<StopOrder>()
+    }
+}
+
+/// The stop orders of a `TriggerOrders` account, oldest first, read and written in place.
+pub struct StopBook<'a> {
+    pub header: RefMut<'a, TriggerOrders>,
+    stops: RefMut<'a, [StopOrder]>,
+}
+
+impl<'a> StopBook<'a> {
+    /// Borrows the data of `loader` for as long as the store is held, like `BookSide::load`.
+    pub fn load(loader: &'a AccountLoader<'_, TriggerOrders>) -> Result<Self> {
+        let data = RefMut::map(loader.as_ref().try_borrow_mut_data()?, |data| &mut data[..]);
+        require!(
+            data.len() >= 8 && data[..8] == *TriggerOrders::DISCRIMINATOR,
+            ErrorCode::AccountDiscriminatorMismatch
+        );
+
+        let (header, stops) = RefMut::map_split(data, |data| {
+            data[8..].split_at_mut(size_of::<TriggerOrders>())
+        });
+        let header: RefMut<TriggerOrders> =
+            RefMut::map(header, |header| bytemuck::from_bytes_mut(header));
+        let stops_space = header.capacity as usize * size_of::<StopOrder>();
+        Ok(StopBook {
+            header,
+            stops: RefMut::map(stops, |stops| {
+                bytemuck::cast_slice_mut(&mut stops[..stops_space])
+            }),
+        })
+    }
+
+    pub fn last_traded_price(&self) -> u64 {
+        self.header.last_traded_price
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.header.len as usize == self.stops.len()
+    }
+
+    pub fn push(&mut self, stop: StopOrder) {
+        let len = self.header.len as usize;
+        self.stops[len] = stop;
+        self.header.len += 1;
+    }
+
+    /// Records a fill at `price` and drags trailing triggers along: a sell stop's trigger up
+    /// to `price` minus its offset, a buy stop's down to `price` plus its offset. Triggers
+    /// never move back.
+    pub fn record_trade(&mut self, price: u64) {
+        self.header.last_traded_price = price;
+
+        let len = self.header.len as usize;
+        for stop in self.stops[..len].iter_mut() {
+            let Some(trailing) = stop.trailing() else {
+                continue;
+            };
+            stop.trigger_price = match stop.side() {
+                Side::Bid => min(stop.trigger_price, price.saturating_add(trailing.of(price))),
+                Side::Ask => max(stop.trigger_price, price.saturating_sub(trailing.of(price))),
+            };
+        }
+    }
+
+    /// Takes out the oldest stop order triggered by the last traded price that is `ready` to
+    /// fire, if any, buys before sells. Triggered orders that are not stay for a later trade
+    /// or crank.
+    pub fn pop_triggered(
+        &mut self,
+        ready: impl Fn(&Side, &StopOrder) -> bool,
+    ) -> Option<(Side, StopOrder)> {
+        let last_price = self.header.last_traded_price;
+        if last_price == 0 {
+            return None;
+        }
+
+        let len = self.header.len as usize;
+        let triggered = |side: Side| {
+            self.stops[..len].iter().position(|stop| {
+                let trigger_price = stop.trigger_price;
+                stop.side() == side
+                    && match side {
+                        Side::Bid => last_price >= trigger_price,
+                        Side::Ask => last_price <= trigger_price,
+                    }
+                    && ready(&side, stop)
+            })
+        };
+        let index = triggered(Side::Bid).or_else(|| triggered(Side::Ask))?;
+        let stop = self.remove(index);
+        Some((stop.side(), stop))
+    }
+
+    /// Removes `owner`'s stop order `order_id` from `side` and returns it.
+    pub fn remove_order(
+        &mut self,
+        side: &Side,
+        owner: &Pubkey,
+        order_id: u64,
+    ) -> Option<StopOrder> {
+        let len = self.header.len as usize;
+        let index = self.stops[..len].iter().position(|stop| {
+            stop.order_id == order_id && stop.owner == *owner && stop.side() == *side
+        })?;
+        Some(self.remove(index))
+    }
+
+    /// Removes the stop order at `index`, keeping the rest in the order they were placed.
+    fn remove(&mut self, index: usize) -> StopOrder {
+        let len = self.header.len as usize;
+        let stop = self.stops[index];
+        self.stops.copy_within(index + 1..len, index);
+        self.header.len -= 1;
+        stop
+    }
 }
 
 #[account]
@@ -38,7 +1263,121 @@ pub struct Market {
     pub quote_vault: Pubkey,
     pub fee_bps: u16,
     pub creator: Pubkey,
+    pub matching: MatchingAlgorithm,
 }
 impl Market {
-    pub const LEN: usize = 32 * 5 + 2;
+    pub const LEN: usize = 32 * 5 + 2 + 2;
+}
+
+/// Takes the exhausted order `order_id` off `book`. An iceberg with reserve left is refilled
+/// instead and loses time priority: it goes behind every other order at its price.
+fn remove_or_refill(
+    book: &mut BookSide<'_>,
+    side: &Side,
+    order_id: u64,
+    references: &PegReferences,
+) {
+    let Some(mut order) = book.remove(order_id) else {
+        return;
+    };
+    if order.hidden_amount == 0 {
+        return;
+    }
+
+    let hidden_amount = order.hidden_amount;
+    order.set_total_amount(hidden_amount);
+    insert_by_price(book, side, order, references);
+}
+
+/// Moves the pegged orders of `book`, the `side` side, to where `references` put them. Only
+/// writes to the side when those moved since it was last priced, and only reorders it when it
+/// has pegged orders.
+fn reprice_side(book: &mut BookSide<'_>, side: &Side, references: &PegReferences) {
+    if PegReferences::last_of(&book.header) == *references {
+        return;
+    }
+    book.header.priced_from_bid = references.best_bid.unwrap_or(0);
+    book.header.priced_from_ask = references.best_ask.unwrap_or(0);
+    if book.header.pegged == 0 {
+        return;
+    }
+
+    let is_pegged = |order: &LimitOrder| order.peg() != Peg::Fixed;
+    for order in book.drain_where(is_pegged) {
+        insert_by_price(book, side, order, references);
+    }
+}
+
+/// Inserts `order` into `book` behind every order at its price or better. Bids rank the
+/// highest price first, so their priority counts down from the top.
+fn insert_by_price(
+    book: &mut BookSide<'_>,
+    side: &Side,
+    order: LimitOrder,
+    references: &PegReferences,
+) {
+    let price = references.rank_price(side, &order);
+    let priority = match side {
+        Side::Bid => u64::MAX - price,
+        Side::Ask => price,
+    };
+    book.insert(priority, order);
+}
+
+/// The live orders of other owners than `taker` at `price` on `side`, from `key` on in
+/// price-time order.
+fn level_from(
+    book: &BookSide,
+    side: &Side,
+    key: u128,
+    price: u64,
+    references: &PegReferences,
+    now: i64,
+    taker: &Pubkey,
+) -> Vec<LimitOrder> {
+    let mut level = Vec::new();
+    let mut cursor = key;
+    while let Some((order_key, order)) = book.first_from(cursor) {
+        if references.price_of(side, &order) != Some(price) {
+            break;
+        }
+        if !order.is_expired(now) && order.owner != *taker {
+            level.push(order);
+        }
+        cursor = order_key + 1;
+    }
+    level
+}
+
+/// Splits `quantity` across orders of `amounts` visible size in proportion to their size,
+/// rounding down. With `top_order_priority` the first order is filled first. What rounding
+/// leaves over goes out in time priority. Needs `quantity` at most the sum of `amounts`.
+fn pro_rata(amounts: &[u64], quantity: u64, top_order_priority: bool) -> Vec<u64> {
+    let mut allocations = vec![0; amounts.len()];
+    let mut left = quantity;
+
+    let shared = if top_order_priority && !amounts.is_empty() {
+        allocations[0] = min(amounts[0], left);
+        left -= allocations[0];
+        1
+    } else {
+        0
+    };
+
+    let total: u128 = amounts[shared..].iter().map(|&amount| amount as u128).sum();
+    if total > 0 {
+        let to_share = left as u128;
+        for (allocation, &amount) in allocations[shared..].iter_mut().zip(&amounts[shared..]) {
+            *allocation = (to_share * amount as u128 / total) as u64;
+            left -= *allocation;
+        }
+    }
+
+    for (allocation, &amount) in allocations.iter_mut().zip(amounts) {
+        let extra = min(amount - *allocation, left);
+        *allocation += extra;
+        left -= extra;
+    }
+
+    allocations
 }
@@ -18,6 +18,8 @@ describe("orderbook-dex", () => {
 
   const market_creator = anchor.web3.Keypair.generate();
   const trader = anchor.web3.Keypair.generate();
+  // Rests orders for the trader to trade against on the markets of single tests
+  const maker = anchor.web3.Keypair.generate();
 
   // We will initialize these in the 'before' block
   let base_mint: anchor.web3.PublicKey;
@@ -26,9 +28,325 @@ describe("orderbook-dex", () => {
   let quote_mint_valut: anchor.web3.PublicKey;
   let trader_base_mint_acc: anchor.web3.PublicKey;
   let trader_quote_mint_acc: anchor.web3.PublicKey;
+  let fee_collector_acc: anchor.web3.PublicKey;
+  let maker_quote_mint_acc: anchor.web3.PublicKey;
+
+  const goodTillCancel = { goodTillCancel: {} };
+  const notPostOnly = { disabled: {} };
+  const noExpiry = new anchor.BN(0);
+  const noClientId = new anchor.BN(0);
+  const cancelTaker = { cancelTaker: {} };
+  const noDisplay = new anchor.BN(0);
+  const noLink = new anchor.BN(0);
+  const notPegged = { fixed: {} };
+  const noPegOffset = new anchor.BN(0);
+
+  // Orders each side of the main market's book has room for, the most a book can be
+  // created with
+  const capacity = 64;
+
+  // Byte sizes of a book side account: the header, then `capacity` slots of a leaf
+  // (key, order) and an inner node for each of the four trees
+  const sideHeaderSize = 8 + 32 + 8 + 4 * 3 + 4 * 4 + 8 * 3;
+  const orderSize = 106;
+  const slotSize = 16 + orderSize + 4 * 9;
+  const sideSize = (capacity) => sideHeaderSize + capacity * slotSize;
+
+  const decodeOrder = (data: Buffer, at: number) => {
+    const u64 = (offset) =>
+      new anchor.BN(data.subarray(at + offset, at + offset + 8), "le");
+    return {
+      owner: new anchor.web3.PublicKey(data.subarray(at, at + 32)),
+      price: u64(32),
+      amount: u64(40),
+      orderId: u64(48),
+      clientOrderId: u64(56),
+      expiresAt: u64(64),
+      selfTradeBehavior: data[at + 72],
+      displayAmount: u64(73),
+      hiddenAmount: u64(81),
+      linkedOrderId: u64(89),
+      peg: data[at + 97],
+      pegOffset: Number(data.readBigInt64LE(at + 98)),
+    };
+  };
+
+  // The slots are a raw slab after the header that the IDL does not describe. A side keeps
+  // its orders in the first `len` slots in no particular order; sorting them by key gives
+  // price-time order
+  const fetchSide = async (sidePda) => {
+    const header = await program.account.orderBookSide.fetch(sidePda);
+    const { data } = await provider.connection.getAccountInfo(sidePda);
+    const leaves = [];
+    for (let i = 0; i < header.len; i++) {
+      const slot = sideHeaderSize + i * slotSize;
+      leaves.push({
+        key: new anchor.BN(data.subarray(slot, slot + 16), "le"),
+        order: decodeOrder(data, slot + 16),
+      });
+    }
+    return {
+      header,
+      orders: leaves.sort((a, b) => a.key.cmp(b.key)).map((leaf) => leaf.order),
+    };
+  };
+
+  // Stop orders follow their header the same way, oldest first, each side told by the parity
+  // of its order id
+  const triggerHeaderSize = 8 + 32 + 8 + 4 * 2;
+  const stopSize = 32 + 1 + 8 * 4 + 1 + 8;
+
+  const fetchTriggerOrders = async (triggerOrdersPda) => {
+    const header = await program.account.triggerOrders.fetch(triggerOrdersPda);
+    const { data } = await provider.connection.getAccountInfo(triggerOrdersPda);
+    const stops = [];
+    for (let i = 0; i < header.len; i++) {
+      const at = triggerHeaderSize + i * stopSize;
+      const u64 = (offset) =>
+        new anchor.BN(data.subarray(at + offset, at + offset + 8), "le");
+      stops.push({
+        owner: new anchor.web3.PublicKey(data.subarray(at, at + 32)),
+        kind: data[at + 32],
+        triggerPrice: u64(33),
+        price: u64(41),
+        amount: u64(49),
+        orderId: u64(57),
+        trailing: data[at + 65],
+        trailingOffset: u64(66),
+      });
+    }
+    return {
+      lastTradedPrice: header.lastTradedPrice,
+      bids: stops.filter((stop) => stop.orderId.isEven()),
+      asks: stops.filter((stop) => stop.orderId.isOdd()),
+    };
+  };
+
+  const fetchOrderBook = async () => {
+    const bids = await fetchSide(bidsPda);
+    const asks = await fetchSide(asksPda);
+    return {
+      market: bids.header.market,
+      capacity: bids.header.capacity,
+      nextBidId: bids.header.nextOrderId,
+      nextAskId: asks.header.nextOrderId,
+      bids: bids.orders,
+      asks: asks.orders,
+    };
+  };
+
+  const balance = async (ata: anchor.web3.PublicKey) =>
+    Number((await provider.connection.getTokenAccountBalance(ata)).value.amount);
+
+  const writable = (pubkey: anchor.web3.PublicKey) => ({
+    pubkey,
+    isSigner: false,
+    isWritable: true,
+  });
+
+  // A market of its own for a test that needs an empty book. Its base mint has no decimals,
+  // so an order's quote is just `amount * price`. The trader and the maker get 1000 base
+  // tokens each
+  const createTestMarket = async (matching) => {
+    const baseMint = await createMint(
+      provider.connection,
+      market_creator,
+      market_creator.publicKey,
+      null,
+      0
+    );
+    const [market] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("market"), baseMint.toBuffer(), quote_mint.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .createMarket(0, matching, 8)
+      .accountsPartial({
+        marketSigner: market_creator.publicKey,
+        baseMint,
+        quoteMint: quote_mint,
+        market,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([market_creator])
+      .rpc();
+
+    const pda = (seed: string) =>
+      anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from(seed), market.toBuffer()],
+        program.programId
+      )[0];
+    const ata = async (mint, owner) =>
+      (
+        await getOrCreateAssociatedTokenAccount(
+          provider.connection,
+          market_creator,
+          mint,
+          owner,
+          true
+        )
+      ).address;
+
+    const testMarket = {
+      baseMint,
+      market,
+      bids: pda("market_bids"),
+      asks: pda("market_asks"),
+      triggerOrders: pda("market_triggerOrders"),
+      baseVault: await ata(baseMint, market),
+      quoteVault: await ata(quote_mint, market),
+      traderBase: await ata(baseMint, trader.publicKey),
+      makerBase: await ata(baseMint, maker.publicKey),
+    };
+    for (const owner of [testMarket.traderBase, testMarket.makerBase]) {
+      await mintTo(
+        provider.connection,
+        market_creator,
+        baseMint,
+        owner,
+        market_creator,
+        1000
+      );
+    }
+    return testMarket;
+  };
+
+  // A good-till-cancel limit order of `owner`, the trader or the maker, on a test market.
+  // Callers add the remaining accounts their fills need and send it
+  const placeOn = (
+    testMarket,
+    owner: anchor.web3.Keypair,
+    side,
+    price: number,
+    amount: number,
+    selfTrade = cancelTaker,
+    link = noLink
+  ) =>
+    program.methods
+      .placeOrder(
+        new anchor.BN(price),
+        new anchor.BN(amount),
+        side,
+        goodTillCancel,
+        notPostOnly,
+        noExpiry,
+        noClientId,
+        selfTrade,
+        noDisplay,
+        link,
+        notPegged,
+        noPegOffset
+      )
+      .accountsPartial({
+        trader: owner.publicKey,
+        market: testMarket.market,
+        bids: testMarket.bids,
+        asks: testMarket.asks,
+        triggerOrders: testMarket.triggerOrders,
+        baseMint: testMarket.baseMint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount:
+          owner === trader ? testMarket.traderBase : testMarket.makerBase,
+        traderQuoteMintAccount:
+          owner === trader ? trader_quote_mint_acc : maker_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([owner]);
+
+  // A stop order of the trader on a test market
+  const placeStopOn = (
+    testMarket,
+    side,
+    kind,
+    triggerPrice: number,
+    price: number,
+    amount: number,
+    trailing = null
+  ) =>
+    program.methods
+      .placeStopOrder(
+        side,
+        kind,
+        new anchor.BN(triggerPrice),
+        new anchor.BN(price),
+        new anchor.BN(amount),
+        trailing
+      )
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: testMarket.market,
+        bookSide: side.bid ? testMarket.bids : testMarket.asks,
+        triggerOrders: testMarket.triggerOrders,
+        baseMint: testMarket.baseMint,
+        quoteMint: quote_mint,
+        vault: side.bid ? testMarket.quoteVault : testMarket.baseVault,
+        traderTokenAccount: side.bid ? trader_quote_mint_acc : testMarket.traderBase,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+  // A post of `owner`, the trader or the maker, on a test market: one side and its vault only
+  const postOn = (
+    testMarket,
+    owner: anchor.web3.Keypair,
+    side,
+    price: number,
+    amount: number
+  ) => {
+    const [ownerBase, ownerQuote] =
+      owner === trader
+        ? [testMarket.traderBase, trader_quote_mint_acc]
+        : [testMarket.makerBase, maker_quote_mint_acc];
+    return program.methods
+      .postOrder(side, new anchor.BN(price), new anchor.BN(amount), noExpiry)
+      .accountsPartial({
+        trader: owner.publicKey,
+        market: testMarket.market,
+        baseMint: testMarket.baseMint,
+        quoteMint: quote_mint,
+        bookSide: side.bid ? testMarket.bids : testMarket.asks,
+        vault: side.bid ? testMarket.quoteVault : testMarket.baseVault,
+        traderTokenAccount: side.bid ? ownerQuote : ownerBase,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([owner]);
+  };
+
+  // Runs the match_order crank on a test market, with the ATAs of the trader, the maker and
+  // the market creator, who cranks, as remaining accounts
+  const crankOn = (testMarket, feeCollector = fee_collector_acc) =>
+    program.methods
+      .matchOrder()
+      .accountsPartial({
+        cranker: market_creator.publicKey,
+        baseMint: testMarket.baseMint,
+        quoteMint: quote_mint,
+        baseMintVault: testMarket.baseVault,
+        quoteMintVault: testMarket.quoteVault,
+        market: testMarket.market,
+        bids: testMarket.bids,
+        asks: testMarket.asks,
+        triggerOrders: testMarket.triggerOrders,
+        feeCollector,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([
+        writable(testMarket.traderBase),
+        writable(testMarket.makerBase),
+        writable(trader_quote_mint_acc),
+        writable(maker_quote_mint_acc),
+        writable(fee_collector_acc),
+      ])
+      .signers([market_creator])
+      .rpc();
 
   let marketPda: anchor.web3.PublicKey;
-  let orderBookPda: anchor.web3.PublicKey;
+  let bidsPda: anchor.web3.PublicKey;
+  let asksPda: anchor.web3.PublicKey;
+  let triggerOrdersPda: anchor.web3.PublicKey;
 
   before("Setup environment", async () => {
     // 1. Fund creator and trader from provider wallet
@@ -50,6 +368,15 @@ describe("orderbook-dex", () => {
     );
     await provider.sendAndConfirm(transferTx2);
 
+    const transferTx3 = new anchor.web3.Transaction().add(
+      anchor.web3.SystemProgram.transfer({
+        fromPubkey: provider.publicKey,
+        toPubkey: maker.publicKey,
+        lamports: 2 * anchor.web3.LAMPORTS_PER_SOL,
+      })
+    );
+    await provider.sendAndConfirm(transferTx3);
+
     // 2. Create Mints
     // Note: createMint returns the PublicKey of the new mint
     base_mint = await createMint(
@@ -74,9 +401,18 @@ describe("orderbook-dex", () => {
       program.programId
     );
 
-    // Derive OrderBook PDA
-    [orderBookPda] = anchor.web3.PublicKey.findProgramAddressSync(
-      [Buffer.from("market_orderBook"), marketPda.toBuffer()],
+    // Derive the PDAs of the two sides of the book
+    [bidsPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("market_bids"), marketPda.toBuffer()],
+      program.programId
+    );
+    [asksPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("market_asks"), marketPda.toBuffer()],
+      program.programId
+    );
+
+    [triggerOrdersPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("market_triggerOrders"), marketPda.toBuffer()],
       program.programId
     );
 
@@ -115,6 +451,23 @@ describe("orderbook-dex", () => {
     );
     trader_quote_mint_acc = traderQuoteAta.address;
 
+    // Fee collector (market creator's quote ATA)
+    const feeCollectorAta = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      market_creator,
+      quote_mint,
+      market_creator.publicKey
+    );
+    fee_collector_acc = feeCollectorAta.address;
+
+    const makerQuoteAta = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      maker,
+      quote_mint,
+      maker.publicKey
+    );
+    maker_quote_mint_acc = makerQuoteAta.address;
+
     // 4. Mint tokens to baseMintVaultAta
     await mintTo(
       provider.connection,
@@ -152,13 +505,22 @@ describe("orderbook-dex", () => {
       market_creator,
       1000 * 10 ** 6 // 1000 Quote tokens
     );
+
+    await mintTo(
+      provider.connection,
+      market_creator,
+      quote_mint,
+      maker_quote_mint_acc,
+      market_creator,
+      1000 * 10 ** 6
+    );
   });
 
   it("Is initialized!", async () => {
     let fee_bps = 30;
 
     const tx = await program.methods
-      .createMarket(fee_bps)
+      .createMarket(fee_bps, { fifo: {} }, capacity)
       .accountsPartial({
         marketSigner: market_creator.publicKey,
         baseMint: base_mint,
@@ -173,9 +535,7 @@ describe("orderbook-dex", () => {
 
     // Fetch the account to verify
     const marketAccount = await program.account.market.fetch(marketPda);
-    const orderBookAccount = await program.account.orderBook.fetch(
-      orderBookPda
-    );
+    const orderBookAccount = await fetchOrderBook();
 
     // Assertions
     assert.equal(marketAccount.feeBps, 30);
@@ -185,8 +545,54 @@ describe("orderbook-dex", () => {
     );
     assert.equal(marketAccount.baseMint.toBase58(), base_mint.toBase58());
     assert.equal(marketAccount.quoteMint.toBase58(), quote_mint.toBase58());
+    assert.ok(marketAccount.matching.fifo);
 
     assert.equal(orderBookAccount.market.toBase58(), marketPda.toBase58());
+    assert.equal(orderBookAccount.capacity, capacity);
+    // 0 means no order, so ids start at 2 for bids and 1 for asks
+    assert.equal(orderBookAccount.nextBidId.toNumber(), 2);
+    assert.equal(orderBookAccount.nextAskId.toNumber(), 1);
+  });
+
+  it("Create market (pro-rata) - stores the matching algorithm and capacity", async () => {
+    const proRataBase = await createMint(
+      provider.connection,
+      market_creator,
+      market_creator.publicKey,
+      null,
+      9
+    );
+    const [proRataMarketPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("market"), proRataBase.toBuffer(), quote_mint.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .createMarket(30, { proRata: { topOrderPriority: true } }, 8)
+      .accountsPartial({
+        marketSigner: market_creator.publicKey,
+        baseMint: proRataBase,
+        quoteMint: quote_mint,
+        market: proRataMarketPda,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([market_creator])
+      .rpc();
+
+    const marketAccount = await program.account.market.fetch(proRataMarketPda);
+    assert.equal(marketAccount.matching.proRata.topOrderPriority, true);
+
+    // A smaller book takes smaller accounts, and less rent
+    for (const seed of ["market_bids", "market_asks"]) {
+      const [sidePda] = anchor.web3.PublicKey.findProgramAddressSync(
+        [Buffer.from(seed), proRataMarketPda.toBuffer()],
+        program.programId
+      );
+      const bookSide = await program.account.orderBookSide.fetch(sidePda);
+      const { data } = await provider.connection.getAccountInfo(sidePda);
+      assert.equal(bookSide.capacity, 8);
+      assert.equal(data.length, sideSize(8));
+    }
   });
 
   it("Place order (Bid)", async () => {
@@ -197,13 +603,27 @@ describe("orderbook-dex", () => {
     let side = { bid: {} };
 
     const tx = await program.methods
-      .placeOrder(price, amount, side)
+      .placeOrder(
+        price,
+        amount,
+        side,
+        goodTillCancel,
+        notPostOnly,
+        noExpiry,
+        noClientId,
+        cancelTaker,
+        noDisplay,
+        noLink,
+        notPegged,
+        noPegOffset
+      )
       .accounts({
         trader: trader.publicKey,
         baseMint: base_mint,
         quoteMint: quote_mint,
         traderBaseMintAccount: trader_base_mint_acc,
         traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
         // The rest are PDAs resolved by Anchor or inferred
         tokenProgram: TOKEN_PROGRAM_ID,
       })
@@ -212,7 +632,7 @@ describe("orderbook-dex", () => {
 
     console.log("Place Order signature", tx);
 
-    let orderBookAccount = await program.account.orderBook.fetch(orderBookPda);
+    let orderBookAccount = await fetchOrderBook();
     console.log("orderBookBids", orderBookAccount.bids[0]);
 
     assert.equal(orderBookAccount.bids.length, 1);
@@ -231,7 +651,20 @@ describe("orderbook-dex", () => {
     let side = { ask: {} };
 
     const tx = await program.methods
-      .placeOrder(price, amount, side)
+      .placeOrder(
+        price,
+        amount,
+        side,
+        goodTillCancel,
+        notPostOnly,
+        noExpiry,
+        noClientId,
+        cancelTaker,
+        noDisplay,
+        noLink,
+        notPegged,
+        noPegOffset
+      )
       .accountsPartial({
         trader: trader.publicKey,
         baseMint: base_mint,
@@ -239,7 +672,9 @@ describe("orderbook-dex", () => {
         traderBaseMintAccount: trader_base_mint_acc,
         traderQuoteMintAccount: trader_quote_mint_acc,
         market: marketPda,
-        orderBook: orderBookPda,
+        bids: bidsPda,
+        asks: asksPda,
+        feeCollector: fee_collector_acc,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .signers([trader])
@@ -247,7 +682,7 @@ describe("orderbook-dex", () => {
 
     console.log("Place Ask Order signature", tx);
 
-    let orderBookAccount = await program.account.orderBook.fetch(orderBookPda);
+    let orderBookAccount = await fetchOrderBook();
     console.log("orderBookAsks", orderBookAccount.asks[0]);
 
     assert.equal(orderBookAccount.asks.length, 1);
@@ -258,21 +693,69 @@ describe("orderbook-dex", () => {
     assert.equal(orderBookAccount.asks[0].price.toNumber(), 12);
   });
 
+  it("Place order - rejects a zero price", async () => {
+    try {
+      await program.methods
+        .placeOrder(
+          new anchor.BN(0),
+          new anchor.BN(1),
+          { ask: {} },
+          goodTillCancel,
+          notPostOnly,
+          noExpiry,
+          noClientId,
+          cancelTaker,
+          noDisplay,
+          noLink,
+          notPegged,
+          noPegOffset
+        )
+        .accounts({
+          trader: trader.publicKey,
+          baseMint: base_mint,
+          quoteMint: quote_mint,
+          traderBaseMintAccount: trader_base_mint_acc,
+          traderQuoteMintAccount: trader_quote_mint_acc,
+          feeCollector: fee_collector_acc,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([trader])
+        .rpc();
+      assert.fail("Should have failed with ErrorValueInvalid");
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "ErrorValueInvalid");
+    }
+  });
+
   it("Stress Test", async () => {
     let amount = new anchor.BN(1);
     let price = new anchor.BN(1);
     let side = { bid: {} };
 
-    for (let i = 1; i <= 49; i++) {
+    for (let i = 1; i < capacity; i++) {
       console.log(`The bid trade no ${i}`);
       const tx = await program.methods
-        .placeOrder(price, amount, side)
+        .placeOrder(
+          price,
+          amount,
+          side,
+          goodTillCancel,
+          notPostOnly,
+          noExpiry,
+          noClientId,
+          cancelTaker,
+          noDisplay,
+          noLink,
+          notPegged,
+          noPegOffset
+        )
         .accounts({
           trader: trader.publicKey,
           baseMint: base_mint,
           quoteMint: quote_mint,
           traderBaseMintAccount: trader_base_mint_acc,
           traderQuoteMintAccount: trader_quote_mint_acc,
+          feeCollector: fee_collector_acc,
           tokenProgram: TOKEN_PROGRAM_ID,
         })
         .signers([trader])
@@ -280,20 +763,34 @@ describe("orderbook-dex", () => {
 
       await provider.connection.confirmTransaction(tx);
     }
-    let orderBookAccount = await program.account.orderBook.fetch(orderBookPda);
+    let orderBookAccount = await fetchOrderBook();
 
-    //See is the orderBookBids are 50 or not
-    assert.equal(orderBookAccount.bids.length, 50);
+    //See is the orderBookBids are full or not
+    assert.equal(orderBookAccount.bids.length, capacity);
 
     try {
       const tx = await program.methods
-        .placeOrder(price, amount, side)
+        .placeOrder(
+          price,
+          amount,
+          side,
+          goodTillCancel,
+          notPostOnly,
+          noExpiry,
+          noClientId,
+          cancelTaker,
+          noDisplay,
+          noLink,
+          notPegged,
+          noPegOffset
+        )
         .accounts({
           trader: trader.publicKey,
           baseMint: base_mint,
           quoteMint: quote_mint,
           traderBaseMintAccount: trader_base_mint_acc,
           traderQuoteMintAccount: trader_quote_mint_acc,
+          feeCollector: fee_collector_acc,
           tokenProgram: TOKEN_PROGRAM_ID,
         })
         .signers([trader])
@@ -306,6 +803,59 @@ describe("orderbook-dex", () => {
       assert.equal(error.error.errorCode.code, "OrderBookFull");
     }
   });
+
+  it("Resize order book - grows a full book and keeps its orders", async () => {
+    const before = await fetchOrderBook();
+    const { lamports: rentBefore } = await provider.connection.getAccountInfo(
+      bidsPda
+    );
+
+    await program.methods
+      .resizeOrderBook(capacity + 4)
+      .accountsPartial({
+        payer: market_creator.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        market: marketPda,
+      })
+      .signers([market_creator])
+      .rpc();
+
+    const after = await fetchOrderBook();
+    assert.equal(after.capacity, capacity + 4);
+    for (const sidePda of [bidsPda, asksPda]) {
+      const { data } = await provider.connection.getAccountInfo(sidePda);
+      assert.equal(data.length, sideSize(capacity + 4));
+    }
+    const triggerOrders = await program.account.triggerOrders.fetch(
+      triggerOrdersPda
+    );
+    assert.equal(triggerOrders.capacity, capacity + 4);
+    const { lamports } = await provider.connection.getAccountInfo(bidsPda);
+    assert.ok(lamports > rentBefore);
+
+    // Both sides read back unchanged
+    const ids = (orders) => orders.map((order) => order.orderId.toNumber());
+    assert.deepEqual(ids(after.bids), ids(before.bids));
+    assert.deepEqual(ids(after.asks), ids(before.asks));
+
+    try {
+      await program.methods
+        .resizeOrderBook(capacity)
+        .accountsPartial({
+          payer: market_creator.publicKey,
+          baseMint: base_mint,
+          quoteMint: quote_mint,
+          market: marketPda,
+        })
+        .signers([market_creator])
+        .rpc();
+      assert.fail("Should have failed with ErrorValueInvalid");
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "ErrorValueInvalid");
+    }
+  });
+
   it("Cancel Order", async () => {
     let orderId = new anchor.BN(4);
     let side = { bid: {} };
@@ -314,41 +864,49 @@ describe("orderbook-dex", () => {
       trader_quote_mint_acc
     );
 
-    const tx = await program.methods
+    // A bid needs only the bids and the quote accounts, the asks side stays unlocked
+    const cancel = program.methods
       .cancelOrder(side, orderId)
       .accountsPartial({
         trader: trader.publicKey,
         market: marketPda,
-        orderBook: orderBookPda,
+        bids: bidsPda,
+        asks: null,
         baseMint: base_mint,
         quoteMint: quote_mint,
 
         quoteMintVault: quote_mint_valut,
-        baseMintVault: base_mint_valut,
-        traderBaseMintAccount: trader_base_mint_acc,
+        baseMintVault: null,
+        traderBaseMintAccount: null,
         traderQuoteMintAccount: trader_quote_mint_acc,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
-      .signers([trader])
-      .rpc();
+      .signers([trader]);
+    const instruction = await cancel.instruction();
+    for (const other of [asksPda, base_mint_valut]) {
+      assert.isFalse(instruction.keys.some((key) => key.pubkey.equals(other)));
+    }
+    const tx = await cancel.rpc();
     await provider.connection.confirmTransaction(tx);
 
-    let orderBookAccount = await program.account.orderBook.fetch(orderBookPda);
+    let orderBookAccount = await fetchOrderBook();
 
-    assert.equal(orderBookAccount.bids.length, 49);
+    assert.equal(orderBookAccount.bids.length, capacity - 1);
 
     let traderQuoteAtaAfter = await provider.connection.getTokenAccountBalance(
       trader_quote_mint_acc
     );
 
-    assert.isAbove(
+    // Refund is the escrow taken at placement: (amount * price) / 10^base_decimals
+    const expectedRefund = Math.floor((1 * 1) / 10 ** 9);
+    assert.equal(
       Number(traderQuoteAtaAfter.value.amount),
-      Number(traderQuoteAtaBefore.value.amount),
-      "token balance is not increase mean refund not happen"
+      Number(traderQuoteAtaBefore.value.amount) + expectedRefund,
+      "refund should return exactly the escrowed quote"
     );
   });
 
-  it("Match Orders - Full Fill (Bid and Ask same amount)", async () => {
+  it("Place order - crossing Ask fills immediately against resting Bid", async () => {
     // Create a second trader (asker)
     const asker = anchor.web3.Keypair.generate();
 
@@ -383,59 +941,70 @@ describe("orderbook-dex", () => {
       base_mint,
       askerBaseAta.address,
       market_creator,
-      100 * 10 ** 9 // 10 Base tokens for asker
-    );
-    await mintTo(
-      provider.connection,
-      market_creator,
-      quote_mint,
-      askerQuoteAta.address,
-      market_creator,
-      100 * 10 ** 6 // 100 Quote tokens for asker
-    );
-
-    // Get initial balances
-    const traderBaseBefore = await provider.connection.getTokenAccountBalance(
-      trader_base_mint_acc
-    );
-    const traderQuoteBefore = await provider.connection.getTokenAccountBalance(
-      trader_quote_mint_acc
-    );
-    const askerBaseBefore = await provider.connection.getTokenAccountBalance(
-      askerBaseAta.address
-    );
-    const askerQuoteBefore = await provider.connection.getTokenAccountBalance(
-      askerQuoteAta.address
+      100 * 10 ** 9 // 100 Base tokens for asker
     );
 
-    // Place BID: price=12, amount=5 (trader wants to buy 5 base tokens)
+    // Place BID: price=11, amount=5 (below the resting ask at 12, so it rests)
     const bidAmount = new anchor.BN(5);
-    const bidPrice = new anchor.BN(12);
+    const bidPrice = new anchor.BN(11);
     const bidSide = { bid: {} };
 
     await program.methods
-      .placeOrder(bidPrice, bidAmount, bidSide)
+      .placeOrder(
+        bidPrice,
+        bidAmount,
+        bidSide,
+        goodTillCancel,
+        notPostOnly,
+        noExpiry,
+        noClientId,
+        cancelTaker,
+        noDisplay,
+        noLink,
+        notPegged,
+        noPegOffset
+      )
       .accounts({
         trader: trader.publicKey,
         baseMint: base_mint,
         quoteMint: quote_mint,
         traderBaseMintAccount: trader_base_mint_acc,
         traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .signers([trader])
       .rpc();
 
-    // Place ASK: price=10, amount=5 (asker wants to sell 5 base tokens)
-    // Price 10 is less than bid price 12, so they should match!
+    const orderBookBefore = await fetchOrderBook();
+    const traderBaseBefore = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+    const askerQuoteBefore = await provider.connection.getTokenAccountBalance(
+      askerQuoteAta.address
+    );
+
+    // Place ASK: price=10, amount=5. It crosses the bid at 11 and fills at the maker's price,
+    // so the bidder's base ATA has to be passed as a remaining account.
     const askAmount = new anchor.BN(5);
     const askPrice = new anchor.BN(10);
     const askSide = { ask: {} };
 
-    //put the ask order here
-
     await program.methods
-      .placeOrder(askPrice, askAmount, askSide)
+      .placeOrder(
+        askPrice,
+        askAmount,
+        askSide,
+        goodTillCancel,
+        notPostOnly,
+        noExpiry,
+        noClientId,
+        cancelTaker,
+        noDisplay,
+        noLink,
+        notPegged,
+        noPegOffset
+      )
       .accountsPartial({
         trader: asker.publicKey,
         baseMint: base_mint,
@@ -443,88 +1012,9 @@ describe("orderbook-dex", () => {
         traderBaseMintAccount: askerBaseAta.address,
         traderQuoteMintAccount: askerQuoteAta.address,
         market: marketPda,
-        orderBook: orderBookPda,
-        tokenProgram: TOKEN_PROGRAM_ID,
-      })
-      .signers([asker])
-      .rpc();
-
-    // Verify ask was placed
-    const afterPlace = await program.account.orderBook.fetch(orderBookPda);
-    console.log("Asks after placing:", afterPlace.asks.length);
-    console.log("Top ask after place:", afterPlace.asks[0]);
-
-    // Create cranker account (who calls match_orders)
-    const cranker = anchor.web3.Keypair.generate();
-    const transferCranker = new anchor.web3.Transaction().add(
-      anchor.web3.SystemProgram.transfer({
-        fromPubkey: provider.publicKey,
-        toPubkey: cranker.publicKey,
-        lamports: 10 * anchor.web3.LAMPORTS_PER_SOL,
-      })
-    );
-    await provider.sendAndConfirm(transferCranker);
-
-    // Create cranker's quote ATA (to receive fee reward)
-    const crankerQuoteAta = await getOrCreateAssociatedTokenAccount(
-      provider.connection,
-      cranker,
-      quote_mint,
-      cranker.publicKey
-    );
-
-    // Create fee collector account (market creator's quote ATA)
-    const feeCollectorAta = await getOrCreateAssociatedTokenAccount(
-      provider.connection,
-      market_creator,
-      quote_mint,
-      market_creator.publicKey
-    );
-
-    // Get orderbook before match
-    const orderBookBefore = await program.account.orderBook.fetch(orderBookPda);
-    console.log("Bids before match:", orderBookBefore.bids.length);
-    console.log("Asks before match:", orderBookBefore.asks.length);
-    console.log("Top bid:", orderBookBefore.bids[0]);
-    console.log("Top ask:", orderBookBefore.asks[0]);
-
-    // Debug: Trace all balances
-    const vaultBaseBefore = await provider.connection.getTokenAccountBalance(
-      base_mint_valut
-    );
-    const vaultQuoteBefore = await provider.connection.getTokenAccountBalance(
-      quote_mint_valut
-    );
-    const traderBaseBeforeLog =
-      await provider.connection.getTokenAccountBalance(trader_base_mint_acc);
-    const traderQuoteBeforeLog =
-      await provider.connection.getTokenAccountBalance(trader_quote_mint_acc);
-    const askerBaseBeforeLog = await provider.connection.getTokenAccountBalance(
-      askerBaseAta.address
-    );
-    const askerQuoteBeforeLog =
-      await provider.connection.getTokenAccountBalance(askerQuoteAta.address);
-
-    console.log("--- BEFORE MATCH ---");
-    console.log("Vault Base:", vaultBaseBefore.value.amount);
-    console.log("Vault Quote:", vaultQuoteBefore.value.amount);
-    console.log("Trader Base:", traderBaseBeforeLog.value.amount);
-    console.log("Trader Quote:", traderQuoteBeforeLog.value.amount);
-    console.log("Asker Base:", askerBaseBeforeLog.value.amount);
-    console.log("Asker Quote:", askerQuoteBeforeLog.value.amount);
-
-    // Call match_orders with remaining_accounts
-    const matchTx = await program.methods
-      .matchOrder()
-      .accountsPartial({
-        cranker: cranker.publicKey,
-        baseMint: base_mint,
-        quoteMint: quote_mint,
-        baseMintVault: base_mint_valut,
-        quoteMintVault: quote_mint_valut,
-        market: marketPda,
-        orderBook: orderBookPda,
-        feeCollector: feeCollectorAta.address,
+        bids: bidsPda,
+        asks: asksPda,
+        feeCollector: fee_collector_acc,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .remainingAccounts([
@@ -533,125 +1023,1730 @@ describe("orderbook-dex", () => {
           isSigner: false,
           isWritable: true,
         },
-        {
-          pubkey: askerQuoteAta.address,
-          isSigner: false,
-          isWritable: true,
-        },
-        {
-          pubkey: crankerQuoteAta.address,
-          isSigner: false,
-          isWritable: true,
-        },
       ])
-      .signers([cranker])
+      .signers([asker])
       .rpc();
 
-    console.log("Match transaction:", matchTx);
-
-    // Get orderbook after match
-    const orderBookAfter = await program.account.orderBook.fetch(orderBookPda);
-    console.log("Bids after match:", orderBookAfter.bids.length);
-    console.log("Asks after match:", orderBookAfter.asks.length);
-
-    // Debug: Trace all balances AFTER
-    const vaultBaseAfter = await provider.connection.getTokenAccountBalance(
-      base_mint_valut
-    );
-    const vaultQuoteAfter = await provider.connection.getTokenAccountBalance(
-      quote_mint_valut
-    );
-    const traderBaseAfterLog = await provider.connection.getTokenAccountBalance(
-      trader_base_mint_acc
-    );
-    const traderQuoteAfterLog =
-      await provider.connection.getTokenAccountBalance(trader_quote_mint_acc);
-    const askerBaseAfterLog = await provider.connection.getTokenAccountBalance(
-      askerBaseAta.address
-    );
-    const askerQuoteAfterLog = await provider.connection.getTokenAccountBalance(
-      askerQuoteAta.address
-    );
-
-    console.log("--- AFTER MATCH ---");
-    console.log("Vault Base:", vaultBaseAfter.value.amount);
-    console.log("Vault Quote:", vaultQuoteAfter.value.amount);
-    console.log("Trader Base:", traderBaseAfterLog.value.amount);
-    console.log("Trader Quote:", traderQuoteAfterLog.value.amount);
-    console.log("Asker Base:", askerBaseAfterLog.value.amount);
-    console.log("Asker Quote:", askerQuoteAfterLog.value.amount);
+    const orderBookAfter = await fetchOrderBook();
 
-    // Assertions:
-    // 1. Bid count should decrease by 1 (our matched bid removed)
+    // 1. The bid was consumed and the ask never rested
     assert.equal(
       orderBookAfter.bids.length,
       orderBookBefore.bids.length - 1,
-      "One bid should be removed after full fill"
+      "Resting bid should be removed after full fill"
     );
-    // 2. Ask should be removed (we only had 1 ask, now 0)
     assert.equal(
       orderBookAfter.asks.length,
-      orderBookBefore.asks.length - 1,
-      "Ask should be removed after full fill"
+      orderBookBefore.asks.length,
+      "Fully filled ask should not rest"
     );
 
     // 2. Trader (bidder) should receive 5 base tokens
     const traderBaseAfter = await provider.connection.getTokenAccountBalance(
       trader_base_mint_acc
     );
-    console.log("Trader Base Before:", traderBaseBefore.value.amount);
-    console.log("Trader Base After:", traderBaseAfter.value.amount);
-
     assert.equal(
       Number(traderBaseAfter.value.amount),
       Number(traderBaseBefore.value.amount) + 5,
       "Bidder should receive base tokens"
     );
 
-    // 3. Asker should receive quote tokens (price 10 * amount 5 = 50) minus fee
+    // 3. Asker should receive quote at the maker price (11) minus fee
     const askerQuoteAfter = await provider.connection.getTokenAccountBalance(
       askerQuoteAta.address
     );
-    const expectedQuote = 50; // 50 quote tokens
-    const feeBps = 30; // 0.3%
-    // Use integer math to match program
-    const expectedFee = Math.floor((expectedQuote * feeBps) / 10000); // = 0
-    const askerFee = Math.floor(expectedFee / 2); // = 0
-
-    console.log("Asker Quote Before:", askerQuoteBefore.value.amount);
-    console.log("Asker Quote After:", askerQuoteAfter.value.amount);
+    // Use integer math to match program: (amount * price) / 10^base_decimals
+    const expectedQuote = Math.floor((5 * 11) / 10 ** 9);
+    const expectedFee = Math.floor((expectedQuote * 30) / 10000);
 
     assert.equal(
       Number(askerQuoteAfter.value.amount),
-      Number(askerQuoteBefore.value.amount) + expectedQuote - askerFee,
+      Number(askerQuoteBefore.value.amount) + expectedQuote - expectedFee,
       "Asker should receive quote tokens minus fee"
     );
+  });
 
-    // 4. Cranker should receive fee reward (10% of total fee)
-    const crankerQuoteAfter = await provider.connection.getTokenAccountBalance(
-      crankerQuoteAta.address
-    );
-    const crankerReward = Math.floor(expectedFee / 10); // = 0
+  it("Match orders - crank fills a pegged bid that repriced across the spread", async () => {
+    // Orders that cross as they come in fill inside place_order. A pegged bid can still
+    // end up crossing when a later order moves its reference, that one is left to the crank
+    const testMarket = await createTestMarket({ fifo: {} });
 
-    assert.equal(
-      Number(crankerQuoteAfter.value.amount),
-      crankerReward,
-      "Cranker should receive 10% fee reward"
-    );
+    await placeOn(testMarket, maker, { ask: {} }, 20, 2).rpc();
+    await placeOn(testMarket, trader, { bid: {} }, 10, 1).rpc();
 
-    // 5. Market creator should receive remaining fee (90%)
-    const feeCollectorBefore = await provider.connection.getTokenAccountBalance(
-      feeCollectorAta.address
-    );
-    const feeCollectorAfter = await provider.connection.getTokenAccountBalance(
-      feeCollectorAta.address
+    // Best bid + 9, never above 100: rests at 19 below the ask
+    await program.methods
+      .placeOrder(
+        new anchor.BN(100),
+        new anchor.BN(2),
+        { bid: {} },
+        goodTillCancel,
+        notPostOnly,
+        noExpiry,
+        noClientId,
+        cancelTaker,
+        noDisplay,
+        noLink,
+        { bestBid: {} },
+        new anchor.BN(9)
+      )
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: testMarket.market,
+        bids: testMarket.bids,
+        asks: testMarket.asks,
+        baseMint: testMarket.baseMint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: testMarket.traderBase,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    // A new best bid of 11 moves the pegged bid up to 20, onto the ask
+    await placeOn(testMarket, trader, { bid: {} }, 11, 1).rpc();
+    assert.equal((await fetchSide(testMarket.bids)).orders.length, 3);
+    assert.equal((await fetchSide(testMarket.asks)).orders.length, 1);
+
+    // Fees only go to the market creator's quote ATA
+    try {
+      await crankOn(testMarket, trader_quote_mint_acc);
+      assert.fail("A fee collector other than the creator's ATA should be rejected");
+    } catch (err) {
+      assert.include(err.toString(), "ConstraintTokenOwner");
+    }
+
+    const traderBaseBefore = await balance(testMarket.traderBase);
+    const traderQuoteBefore = await balance(trader_quote_mint_acc);
+    const makerQuoteBefore = await balance(maker_quote_mint_acc);
+
+    await crankOn(testMarket);
+
+    const bids = (await fetchSide(testMarket.bids)).orders;
+    const asks = (await fetchSide(testMarket.asks)).orders;
+    assert.equal(asks.length, 0);
+    assert.deepEqual(
+      bids.map((bid) => bid.price.toNumber()),
+      [11, 10]
     );
-    const marketCreatorFee = expectedFee - crankerReward; // = 0
 
+    // 2 filled at the ask's 20, the pegged bid escrowed at its limit of 100 gets the rest back
+    assert.equal(await balance(testMarket.traderBase), traderBaseBefore + 2);
+    assert.equal(await balance(maker_quote_mint_acc), makerQuoteBefore + 40);
     assert.equal(
-      Number(feeCollectorAfter.value.amount),
-      Number(feeCollectorBefore.value.amount) + marketCreatorFee,
-      "Market creator should receive 90% of fee"
+      await balance(trader_quote_mint_acc),
+      traderQuoteBefore + 2 * 100 - 40
     );
   });
+
+  it("Market order - sweeps up to worst price and never rests", async () => {
+    // Best bid is the trader's own bid at 10, everything else rests at 1. Self-trade
+    // prevention cancels the own bid instead of filling it.
+    const amount = new anchor.BN(3);
+    const worstPrice = new anchor.BN(5);
+    const side = { ask: {} };
+
+    const orderBookBefore = await fetchOrderBook();
+
+    await program.methods
+      .placeMarketOrder(amount, worstPrice, side, { cancelMaker: {} })
+      .accounts({
+        trader: trader.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([
+        {
+          pubkey: trader_base_mint_acc,
+          isSigner: false,
+          isWritable: true,
+        },
+      ])
+      .signers([trader])
+      .rpc();
+
+    const orderBookAfter = await fetchOrderBook();
+
+    // Only the bid at 10 is within the worst price; the rest is refunded, not rested
+    assert.equal(orderBookAfter.bids.length, orderBookBefore.bids.length - 1);
+    assert.equal(orderBookAfter.asks.length, orderBookBefore.asks.length);
+  });
+
+  it("Market order - fills level by level and stops at the worst price", async () => {
+    const testMarket = await createTestMarket({ fifo: {} });
+    await placeOn(testMarket, maker, { ask: {} }, 10, 2).rpc();
+    await placeOn(testMarket, maker, { ask: {} }, 11, 3).rpc();
+    await placeOn(testMarket, maker, { ask: {} }, 13, 4).rpc();
+    const traderBaseBefore = await balance(testMarket.traderBase);
+    const traderQuoteBefore = await balance(trader_quote_mint_acc);
+    const makerQuoteBefore = await balance(maker_quote_mint_acc);
+
+    // Buy 10 paying at most 12: the asks at 10 and 11 fill, the one at 13 is beyond it
+    await program.methods
+      .placeMarketOrder(new anchor.BN(10), new anchor.BN(12), { bid: {} }, cancelTaker)
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: testMarket.market,
+        bids: testMarket.bids,
+        asks: testMarket.asks,
+        triggerOrders: testMarket.triggerOrders,
+        baseMint: testMarket.baseMint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: testMarket.traderBase,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([writable(maker_quote_mint_acc)])
+      .signers([trader])
+      .rpc();
+
+    const asks = (await fetchSide(testMarket.asks)).orders;
+    assert.deepEqual(
+      asks.map((ask) => [ask.price.toNumber(), ask.amount.toNumber()]),
+      [[13, 4]]
+    );
+    assert.equal((await fetchSide(testMarket.bids)).orders.length, 0);
+    // 2 at 10 and 3 at 11; the escrow taken at 12 for the other 5 comes back
+    assert.equal(await balance(testMarket.traderBase), traderBaseBefore + 5);
+    assert.equal(
+      await balance(trader_quote_mint_acc),
+      traderQuoteBefore - (2 * 10 + 3 * 11)
+    );
+    assert.equal(
+      await balance(maker_quote_mint_acc),
+      makerQuoteBefore + 2 * 10 + 3 * 11
+    );
+  });
+
+  it("Place order (IOC) - unfilled remainder does not rest", async () => {
+    // Best ask is 12, so an IOC bid at 11 has nothing to fill against
+    const amount = new anchor.BN(2);
+    const price = new anchor.BN(11);
+    const side = { bid: {} };
+
+    const orderBookBefore = await fetchOrderBook();
+
+    await program.methods
+      .placeOrder(
+        price,
+        amount,
+        side,
+        { immediateOrCancel: {} },
+        notPostOnly,
+        noExpiry,
+        noClientId,
+        cancelTaker,
+        noDisplay,
+        noLink,
+        notPegged,
+        noPegOffset
+      )
+      .accounts({
+        trader: trader.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const orderBookAfter = await fetchOrderBook();
+
+    assert.equal(orderBookAfter.bids.length, orderBookBefore.bids.length);
+    assert.equal(
+      orderBookAfter.nextBidId.toNumber(),
+      orderBookBefore.nextBidId.toNumber() + 2
+    );
+  });
+
+  it("Place order (FOK) - fails when the book cannot fill it in full", async () => {
+    // Only 1 unit is offered at 12
+    const amount = new anchor.BN(5);
+    const price = new anchor.BN(12);
+    const side = { bid: {} };
+
+    try {
+      await program.methods
+        .placeOrder(
+          price,
+          amount,
+          side,
+          { fillOrKill: {} },
+          notPostOnly,
+          noExpiry,
+          noClientId,
+          cancelTaker,
+          noDisplay,
+          noLink,
+          notPegged,
+          noPegOffset
+        )
+        .accounts({
+          trader: trader.publicKey,
+          baseMint: base_mint,
+          quoteMint: quote_mint,
+          traderBaseMintAccount: trader_base_mint_acc,
+          traderQuoteMintAccount: trader_quote_mint_acc,
+          feeCollector: fee_collector_acc,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([trader])
+        .rpc();
+
+      assert.fail("Should have failed with InsufficientLiquidity");
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "InsufficientLiquidity");
+    }
+  });
+
+  it("Place order (post-only) - rejects or slides a crossing order", async () => {
+    // Best ask is 12, so a post-only bid at 12 would take
+    const amount = new anchor.BN(1);
+    const price = new anchor.BN(12);
+    const side = { bid: {} };
+    const accounts = {
+      trader: trader.publicKey,
+      baseMint: base_mint,
+      quoteMint: quote_mint,
+      traderBaseMintAccount: trader_base_mint_acc,
+      traderQuoteMintAccount: trader_quote_mint_acc,
+      feeCollector: fee_collector_acc,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+
+    try {
+      await program.methods
+        .placeOrder(
+          price,
+          amount,
+          side,
+          goodTillCancel,
+          { reject: {} },
+          noExpiry,
+          noClientId,
+          cancelTaker,
+          noDisplay,
+          noLink,
+          notPegged,
+          noPegOffset
+        )
+        .accounts(accounts)
+        .signers([trader])
+        .rpc();
+
+      assert.fail("Should have failed with PostOnlyWouldCross");
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "PostOnlyWouldCross");
+    }
+
+    const orderBookBefore = await fetchOrderBook();
+
+    await program.methods
+      .placeOrder(
+        price,
+        amount,
+        side,
+        goodTillCancel,
+        { slide: {} },
+        noExpiry,
+        noClientId,
+        cancelTaker,
+        noDisplay,
+        noLink,
+        notPegged,
+        noPegOffset
+      )
+      .accounts(accounts)
+      .signers([trader])
+      .rpc();
+
+    const orderBookAfter = await fetchOrderBook();
+    const slid = orderBookAfter.bids.find(
+      (bid) => bid.orderId.toNumber() === orderBookBefore.nextBidId.toNumber()
+    );
+
+    // Slid one tick behind the best ask and rested without taking
+    assert.equal(slid.price.toNumber(), 11);
+    assert.equal(orderBookAfter.asks.length, orderBookBefore.asks.length);
+  });
+
+  it("Sweep expired orders - refunds escrow of orders past their expiry", async () => {
+    // Ask far above the book that expires in 2 seconds
+    const amount = new anchor.BN(1);
+    const price = new anchor.BN(100);
+    const side = { ask: {} };
+    const expiresAt = new anchor.BN(Math.floor(Date.now() / 1000) + 2);
+
+    await program.methods
+      .placeOrder(
+        price,
+        amount,
+        side,
+        goodTillCancel,
+        notPostOnly,
+        expiresAt,
+        noClientId,
+        cancelTaker,
+        noDisplay,
+        noLink,
+        notPegged,
+        noPegOffset
+      )
+      .accounts({
+        trader: trader.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const orderBookBefore = await fetchOrderBook();
+    const traderBaseBefore = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+
+    await new Promise((resolve) => setTimeout(resolve, 4000));
+
+    await program.methods
+      .sweepExpiredOrders(side)
+      .accountsPartial({
+        cranker: provider.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        market: marketPda,
+        bookSide: asksPda,
+        vault: base_mint_valut,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([
+        {
+          pubkey: trader_base_mint_acc,
+          isSigner: false,
+          isWritable: true,
+        },
+      ])
+      .rpc();
+
+    const orderBookAfter = await fetchOrderBook();
+    const traderBaseAfter = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+
+    assert.equal(orderBookAfter.asks.length, orderBookBefore.asks.length - 1);
+    assert.equal(
+      Number(traderBaseAfter.value.amount),
+      Number(traderBaseBefore.value.amount) + 1,
+      "Expired ask escrow should be refunded"
+    );
+  });
+
+  it("Cancel order by client order id", async () => {
+    // 1 whole base token at price 5 escrows 5 quote units
+    const amount = new anchor.BN(10 ** 9);
+    const price = new anchor.BN(5);
+    const side = { bid: {} };
+    const clientOrderId = new anchor.BN(42);
+
+    const orderBookBefore = await fetchOrderBook();
+    const traderQuoteBefore = await provider.connection.getTokenAccountBalance(
+      trader_quote_mint_acc
+    );
+
+    await program.methods
+      .placeOrder(
+        price,
+        amount,
+        side,
+        goodTillCancel,
+        notPostOnly,
+        noExpiry,
+        clientOrderId,
+        cancelTaker,
+        noDisplay,
+        noLink,
+        notPegged,
+        noPegOffset
+      )
+      .accounts({
+        trader: trader.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const placed = await fetchOrderBook();
+    assert.equal(placed.bids.length, orderBookBefore.bids.length + 1);
+
+    await program.methods
+      .cancelOrderByClientId(clientOrderId)
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: marketPda,
+        bids: bidsPda,
+        asks: asksPda,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        quoteMintVault: quote_mint_valut,
+        baseMintVault: base_mint_valut,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const orderBookAfter = await fetchOrderBook();
+    const traderQuoteAfter = await provider.connection.getTokenAccountBalance(
+      trader_quote_mint_acc
+    );
+
+    assert.equal(orderBookAfter.bids.length, orderBookBefore.bids.length);
+    assert.equal(
+      traderQuoteAfter.value.amount,
+      traderQuoteBefore.value.amount,
+      "escrow should be fully refunded"
+    );
+  });
+
+  it("Cancel all orders - removes every order of the trader", async () => {
+    // Separate trader so the main trader's resting orders are left alone
+    const quoter = anchor.web3.Keypair.generate();
+    await provider.sendAndConfirm(
+      new anchor.web3.Transaction().add(
+        anchor.web3.SystemProgram.transfer({
+          fromPubkey: provider.publicKey,
+          toPubkey: quoter.publicKey,
+          lamports: 2 * anchor.web3.LAMPORTS_PER_SOL,
+        })
+      )
+    );
+
+    const quoterBaseAta = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      quoter,
+      base_mint,
+      quoter.publicKey
+    );
+    const quoterQuoteAta = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      quoter,
+      quote_mint,
+      quoter.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      market_creator,
+      base_mint,
+      quoterBaseAta.address,
+      market_creator,
+      10 * 10 ** 9
+    );
+    await mintTo(
+      provider.connection,
+      market_creator,
+      quote_mint,
+      quoterQuoteAta.address,
+      market_creator,
+      100 * 10 ** 6
+    );
+
+    const orderBookBefore = await fetchOrderBook();
+
+    // One bid below the book and one ask above it, neither crosses
+    const quotes = [
+      { side: { bid: {} }, price: new anchor.BN(2) },
+      { side: { ask: {} }, price: new anchor.BN(50) },
+    ];
+    for (const quote of quotes) {
+      await program.methods
+        .placeOrder(
+          quote.price,
+          new anchor.BN(10 ** 9),
+          quote.side,
+          goodTillCancel,
+          notPostOnly,
+          noExpiry,
+          noClientId,
+          cancelTaker,
+          noDisplay,
+          noLink,
+          notPegged,
+          noPegOffset
+        )
+        .accountsPartial({
+          trader: quoter.publicKey,
+          baseMint: base_mint,
+          quoteMint: quote_mint,
+          traderBaseMintAccount: quoterBaseAta.address,
+          traderQuoteMintAccount: quoterQuoteAta.address,
+          market: marketPda,
+          bids: bidsPda,
+          asks: asksPda,
+          feeCollector: fee_collector_acc,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([quoter])
+        .rpc();
+    }
+
+    await program.methods
+      .cancelAllOrders(null)
+      .accountsPartial({
+        trader: quoter.publicKey,
+        market: marketPda,
+        bids: bidsPda,
+        asks: asksPda,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        quoteMintVault: quote_mint_valut,
+        baseMintVault: base_mint_valut,
+        traderBaseMintAccount: quoterBaseAta.address,
+        traderQuoteMintAccount: quoterQuoteAta.address,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([quoter])
+      .rpc();
+
+    const orderBookAfter = await fetchOrderBook();
+    const quoterBaseAfter = await provider.connection.getTokenAccountBalance(
+      quoterBaseAta.address
+    );
+    const quoterQuoteAfter = await provider.connection.getTokenAccountBalance(
+      quoterQuoteAta.address
+    );
+
+    assert.equal(orderBookAfter.bids.length, orderBookBefore.bids.length);
+    assert.equal(orderBookAfter.asks.length, orderBookBefore.asks.length);
+    assert.equal(Number(quoterBaseAfter.value.amount), 10 * 10 ** 9);
+    assert.equal(Number(quoterQuoteAfter.value.amount), 100 * 10 ** 6);
+  });
+
+  it("Amend order - keeps the order id and moves only the escrow difference", async () => {
+    const clientOrderId = new anchor.BN(7);
+    const side = { bid: {} };
+
+    // 1 base token at price 3 escrows 3 quote units
+    await program.methods
+      .placeOrder(
+        new anchor.BN(3),
+        new anchor.BN(10 ** 9),
+        side,
+        goodTillCancel,
+        notPostOnly,
+        noExpiry,
+        clientOrderId,
+        cancelTaker,
+        noDisplay,
+        noLink,
+        notPegged,
+        noPegOffset
+      )
+      .accounts({
+        trader: trader.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const placed = await fetchOrderBook();
+    const order = placed.bids.find((bid) => bid.clientOrderId.toNumber() === 7);
+    const traderQuoteBefore = await provider.connection.getTokenAccountBalance(
+      trader_quote_mint_acc
+    );
+
+    // 2 base tokens at price 4 escrow 8, so 5 more are topped up
+    await program.methods
+      .amendOrder(
+        side,
+        order.orderId,
+        new anchor.BN(4),
+        new anchor.BN(2 * 10 ** 9)
+      )
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: marketPda,
+        bookSide: bidsPda,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        vault: quote_mint_valut,
+        traderTokenAccount: trader_quote_mint_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const amendedBook = await fetchOrderBook();
+    const amended = amendedBook.bids.find(
+      (bid) => bid.orderId.toNumber() === order.orderId.toNumber()
+    );
+    const traderQuoteAfter = await provider.connection.getTokenAccountBalance(
+      trader_quote_mint_acc
+    );
+
+    assert.equal(amended.price.toNumber(), 4);
+    assert.equal(amended.amount.toNumber(), 2 * 10 ** 9);
+    assert.equal(amendedBook.bids.length, placed.bids.length);
+    assert.equal(
+      Number(traderQuoteAfter.value.amount),
+      Number(traderQuoteBefore.value.amount) - 5
+    );
+
+    // Clean up so later tests see the same book
+    await program.methods
+      .cancelOrderByClientId(clientOrderId)
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: marketPda,
+        bids: bidsPda,
+        asks: asksPda,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        quoteMintVault: quote_mint_valut,
+        baseMintVault: base_mint_valut,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+  });
+
+  it("Place orders - rests a batch with one escrow transfer per mint", async () => {
+    const orderBookBefore = await fetchOrderBook();
+    const traderBaseBefore = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+    const traderQuoteBefore = await provider.connection.getTokenAccountBalance(
+      trader_quote_mint_acc
+    );
+
+    const orders = [
+      {
+        side: { bid: {} },
+        price: new anchor.BN(2),
+        amount: new anchor.BN(10 ** 9),
+        clientOrderId: new anchor.BN(101),
+      },
+      {
+        side: { ask: {} },
+        price: new anchor.BN(60),
+        amount: new anchor.BN(10 ** 9),
+        clientOrderId: new anchor.BN(102),
+      },
+    ];
+
+    await program.methods
+      .placeOrders(orders)
+      .accounts({
+        trader: trader.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const orderBookAfter = await fetchOrderBook();
+    const traderBaseAfter = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+    const traderQuoteAfter = await provider.connection.getTokenAccountBalance(
+      trader_quote_mint_acc
+    );
+
+    assert.equal(orderBookAfter.bids.length, orderBookBefore.bids.length + 1);
+    assert.equal(orderBookAfter.asks.length, orderBookBefore.asks.length + 1);
+    assert.equal(
+      Number(traderQuoteAfter.value.amount),
+      Number(traderQuoteBefore.value.amount) - 2
+    );
+    assert.equal(
+      Number(traderBaseAfter.value.amount),
+      Number(traderBaseBefore.value.amount) - 10 ** 9
+    );
+
+    // Clean up so later tests see the same book
+    for (const order of orders) {
+      await program.methods
+        .cancelOrderByClientId(order.clientOrderId)
+        .accountsPartial({
+          trader: trader.publicKey,
+          market: marketPda,
+          bids: bidsPda,
+          asks: asksPda,
+          baseMint: base_mint,
+          quoteMint: quote_mint,
+          quoteMintVault: quote_mint_valut,
+          baseMintVault: base_mint_valut,
+          traderBaseMintAccount: trader_base_mint_acc,
+          traderQuoteMintAccount: trader_quote_mint_acc,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([trader])
+        .rpc();
+    }
+  });
+
+  it("Self-trade prevention - cancel taker leaves the own resting bid alone", async () => {
+    // The trader's own slid post-only bid rests at 11
+    const orderBookBefore = await fetchOrderBook();
+
+    await program.methods
+      .placeOrder(
+        new anchor.BN(11),
+        new anchor.BN(1),
+        { ask: {} },
+        goodTillCancel,
+        notPostOnly,
+        noExpiry,
+        noClientId,
+        cancelTaker,
+        noDisplay,
+        noLink,
+        notPegged,
+        noPegOffset
+      )
+      .accounts({
+        trader: trader.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const orderBookAfter = await fetchOrderBook();
+
+    // Neither filled nor rested
+    assert.equal(orderBookAfter.bids.length, orderBookBefore.bids.length);
+    assert.equal(orderBookAfter.asks.length, orderBookBefore.asks.length);
+  });
+
+  // The trader rests an ask of 5 at 10, then bids 3 at 10 into it with `selfTrade`.
+  // Returns what is left in the book and how the trader's balances moved overall
+  const selfTradeOnTestMarket = async (selfTrade) => {
+    const testMarket = await createTestMarket({ fifo: {} });
+    const baseBefore = await balance(testMarket.traderBase);
+    const quoteBefore = await balance(trader_quote_mint_acc);
+
+    await placeOn(testMarket, trader, { ask: {} }, 10, 5).rpc();
+    await placeOn(testMarket, trader, { bid: {} }, 10, 3, selfTrade).rpc();
+
+    return {
+      bids: (await fetchSide(testMarket.bids)).orders,
+      asks: (await fetchSide(testMarket.asks)).orders,
+      baseMoved: (await balance(testMarket.traderBase)) - baseBefore,
+      quoteMoved: (await balance(trader_quote_mint_acc)) - quoteBefore,
+    };
+  };
+
+  it("Self-trade prevention - cancel maker refunds the own ask and rests the bid", async () => {
+    const { bids, asks, baseMoved, quoteMoved } = await selfTradeOnTestMarket({
+      cancelMaker: {},
+    });
+
+    assert.equal(asks.length, 0);
+    assert.equal(bids.length, 1);
+    assert.equal(bids[0].amount.toNumber(), 3);
+    // The ask's 5 base came back, the resting bid holds 3 * 10 quote
+    assert.equal(baseMoved, 0);
+    assert.equal(quoteMoved, -30);
+  });
+
+  it("Self-trade prevention - cancel both refunds the own ask and the bid", async () => {
+    const { bids, asks, baseMoved, quoteMoved } = await selfTradeOnTestMarket({
+      cancelBoth: {},
+    });
+
+    assert.equal(asks.length, 0);
+    assert.equal(bids.length, 0);
+    assert.equal(baseMoved, 0);
+    assert.equal(quoteMoved, 0);
+  });
+
+  it("Self-trade prevention - decrement and cancel shrinks both by the overlap", async () => {
+    const { bids, asks, baseMoved, quoteMoved } = await selfTradeOnTestMarket({
+      decrementAndCancel: {},
+    });
+
+    // The overlap of 3 is taken off the ask and refunded, the bid is used up by it
+    assert.equal(bids.length, 0);
+    assert.equal(asks.length, 1);
+    assert.equal(asks[0].amount.toNumber(), 2);
+    assert.equal(baseMoved, -2);
+    assert.equal(quoteMoved, 0);
+  });
+
+  it("Self-trade prevention - cancelling a linked maker cancels its other leg", async () => {
+    const testMarket = await createTestMarket({ fifo: {} });
+    const baseBefore = await balance(testMarket.traderBase);
+
+    await placeOn(testMarket, trader, { ask: {} }, 10, 5).rpc();
+    const [firstLeg] = (await fetchSide(testMarket.asks)).orders;
+    await placeOn(
+      testMarket,
+      trader,
+      { ask: {} },
+      12,
+      2,
+      cancelTaker,
+      firstLeg.orderId
+    ).rpc();
+
+    await placeOn(testMarket, trader, { bid: {} }, 10, 3, {
+      cancelMaker: {},
+    }).rpc();
+
+    // Both legs are gone and refunded, the bid rests
+    assert.equal((await fetchSide(testMarket.asks)).orders.length, 0);
+    assert.equal((await fetchSide(testMarket.bids)).orders.length, 1);
+    assert.equal(await balance(testMarket.traderBase), baseBefore);
+  });
+
+  it("Place order (iceberg) - shows only the display amount", async () => {
+    const amount = new anchor.BN(5);
+    const displayAmount = new anchor.BN(2);
+    const clientOrderId = new anchor.BN(55);
+
+    const traderBaseBefore = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+
+    await program.methods
+      .placeOrder(
+        new anchor.BN(1000),
+        amount,
+        { ask: {} },
+        goodTillCancel,
+        notPostOnly,
+        noExpiry,
+        clientOrderId,
+        cancelTaker,
+        displayAmount,
+        noLink,
+        notPegged,
+        noPegOffset
+      )
+      .accounts({
+        trader: trader.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const orderBook = await fetchOrderBook();
+    const iceberg = orderBook.asks.find((ask) =>
+      ask.clientOrderId.eq(clientOrderId)
+    );
+    assert.equal(iceberg.amount.toNumber(), 2);
+    assert.equal(iceberg.hiddenAmount.toNumber(), 3);
+
+    // Cancelling refunds the hidden reserve as well
+    await program.methods
+      .cancelOrderByClientId(clientOrderId)
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: marketPda,
+        bids: bidsPda,
+        asks: asksPda,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        quoteMintVault: quote_mint_valut,
+        baseMintVault: base_mint_valut,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const traderBaseAfter = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+    assert.equal(traderBaseAfter.value.amount, traderBaseBefore.value.amount);
+  });
+
+  it("Stop order - escrowed while waiting for its trigger, refunded on cancel", async () => {
+    // Sell stop far below the market, it never triggers during the test
+    const amount = new anchor.BN(3);
+
+    const traderBaseBefore = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+
+    await program.methods
+      .placeStopOrder(
+        { ask: {} },
+        { stopLimit: {} },
+        new anchor.BN(2),
+        new anchor.BN(1),
+        amount,
+        null
+      )
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: marketPda,
+        bookSide: asksPda,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        vault: base_mint_valut,
+        traderTokenAccount: trader_base_mint_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const triggerOrders = await fetchTriggerOrders(triggerOrdersPda);
+    const stop = triggerOrders.asks[triggerOrders.asks.length - 1];
+    assert.equal(stop.triggerPrice.toNumber(), 2);
+    assert.equal(stop.amount.toNumber(), 3);
+
+    const traderBaseEscrowed =
+      await provider.connection.getTokenAccountBalance(trader_base_mint_acc);
+    assert.equal(
+      Number(traderBaseBefore.value.amount) -
+        Number(traderBaseEscrowed.value.amount),
+      3
+    );
+
+    await program.methods
+      .cancelStopOrder({ ask: {} }, stop.orderId)
+      .accounts({
+        trader: trader.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const triggerOrdersAfter = await fetchTriggerOrders(triggerOrdersPda);
+    const traderBaseAfter = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+    assert.equal(triggerOrdersAfter.asks.length, triggerOrders.asks.length - 1);
+    assert.equal(traderBaseAfter.value.amount, traderBaseBefore.value.amount);
+  });
+
+  it("Stop order - fires as soon as a taken fill reaches its trigger", async () => {
+    const testMarket = await createTestMarket({ fifo: {} });
+    await placeOn(testMarket, maker, { bid: {} }, 14, 5).rpc();
+
+    // Sell 2 at 10 or better once the price falls to 15
+    await placeStopOn(testMarket, { ask: {} }, { stopLoss: {} }, 15, 10, 2);
+    const traderQuoteBefore = await balance(trader_quote_mint_acc);
+
+    // Selling 1 into the bid trades at 14, which triggers the stop in the same instruction.
+    // The stop's own fill needs the trader's ATAs and the maker's base ATA
+    await placeOn(testMarket, trader, { ask: {} }, 14, 1)
+      .remainingAccounts([
+        writable(testMarket.makerBase),
+        writable(testMarket.traderBase),
+        writable(trader_quote_mint_acc),
+      ])
+      .rpc();
+
+    const triggerOrders = await fetchTriggerOrders(testMarket.triggerOrders);
+    assert.equal(triggerOrders.asks.length, 0);
+    assert.equal(triggerOrders.lastTradedPrice.toNumber(), 14);
+
+    const bids = (await fetchSide(testMarket.bids)).orders;
+    assert.equal(bids.length, 1);
+    assert.equal(bids[0].amount.toNumber(), 2);
+    // 1 sold by the order and 2 by the stop, all at the bid's 14
+    assert.equal(
+      await balance(trader_quote_mint_acc),
+      traderQuoteBefore + 3 * 14
+    );
+  });
+
+  it("One-cancels-other - cancelling one leg cancels the other", async () => {
+    const traderBaseBefore = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+
+    const placeAsk = (price: number, clientOrderId: number, link: anchor.BN) =>
+      program.methods
+        .placeOrder(
+          new anchor.BN(price),
+          new anchor.BN(2),
+          { ask: {} },
+          goodTillCancel,
+          notPostOnly,
+          noExpiry,
+          new anchor.BN(clientOrderId),
+          cancelTaker,
+          noDisplay,
+          link,
+          notPegged,
+          noPegOffset
+        )
+        .accounts({
+          trader: trader.publicKey,
+          baseMint: base_mint,
+          quoteMint: quote_mint,
+          traderBaseMintAccount: trader_base_mint_acc,
+          traderQuoteMintAccount: trader_quote_mint_acc,
+          feeCollector: fee_collector_acc,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([trader])
+        .rpc();
+
+    await placeAsk(2000, 71, noLink);
+    const orderBook = await fetchOrderBook();
+    const firstLeg = orderBook.asks.find((ask) =>
+      ask.clientOrderId.eq(new anchor.BN(71))
+    );
+
+    await placeAsk(3000, 72, firstLeg.orderId);
+    const linked = await fetchOrderBook();
+    const secondLeg = linked.asks.find((ask) =>
+      ask.clientOrderId.eq(new anchor.BN(72))
+    );
+    assert.ok(secondLeg.linkedOrderId.eq(firstLeg.orderId));
+
+    await program.methods
+      .cancelOrder({ ask: {} }, secondLeg.orderId)
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: marketPda,
+        bids: bidsPda,
+        asks: asksPda,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        quoteMintVault: quote_mint_valut,
+        baseMintVault: base_mint_valut,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const orderBookAfter = await fetchOrderBook();
+    const traderBaseAfter = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+    assert.equal(orderBookAfter.asks.length, orderBook.asks.length - 1);
+    assert.equal(traderBaseAfter.value.amount, traderBaseBefore.value.amount);
+  });
+
+  it("Trailing stop order - stores its offset next to the trigger", async () => {
+    await program.methods
+      .placeStopOrder(
+        { ask: {} },
+        { stopLoss: {} },
+        new anchor.BN(2),
+        new anchor.BN(1),
+        new anchor.BN(1),
+        { bps: { bps: 500 } }
+      )
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: marketPda,
+        bookSide: asksPda,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        vault: base_mint_valut,
+        traderTokenAccount: trader_base_mint_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const triggerOrders = await fetchTriggerOrders(triggerOrdersPda);
+    const stop = triggerOrders.asks[triggerOrders.asks.length - 1];
+    assert.equal(stop.triggerPrice.toNumber(), 2);
+    // A trailing offset in bps
+    assert.equal(stop.trailing, 2);
+    assert.equal(stop.trailingOffset.toNumber(), 500);
+
+    await program.methods
+      .cancelStopOrder({ ask: {} }, stop.orderId)
+      .accounts({
+        trader: trader.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+  });
+
+  it("Trailing stop order - fills drag the trigger up until a drop fires it", async () => {
+    const testMarket = await createTestMarket({ fifo: {} });
+    await placeOn(testMarket, maker, { bid: {} }, 20, 1).rpc();
+    await placeOn(testMarket, maker, { bid: {} }, 16, 3).rpc();
+
+    // Sell 1 at any price once the price falls 3 below its high
+    await placeStopOn(testMarket, { ask: {} }, { stopLoss: {} }, 1, 1, 1, {
+      absolute: { offset: new anchor.BN(3) },
+    });
+    const sellInto = (price: number, remainingAccounts) =>
+      placeOn(testMarket, trader, { ask: {} }, price, 1)
+        .remainingAccounts(remainingAccounts.map(writable))
+        .rpc();
+
+    // A trade at 20 drags the trigger from 1 up to 17
+    await sellInto(20, [testMarket.makerBase]);
+    let triggerOrders = await fetchTriggerOrders(testMarket.triggerOrders);
+    assert.equal(triggerOrders.asks.length, 1);
+    assert.equal(triggerOrders.asks[0].triggerPrice.toNumber(), 17);
+
+    // The next one at 16 is below it and fires the stop against the same bid
+    await sellInto(16, [
+      testMarket.makerBase,
+      testMarket.traderBase,
+      trader_quote_mint_acc,
+    ]);
+    triggerOrders = await fetchTriggerOrders(testMarket.triggerOrders);
+    assert.equal(triggerOrders.asks.length, 0);
+
+    const bids = (await fetchSide(testMarket.bids)).orders;
+    assert.equal(bids.length, 1);
+    assert.equal(bids[0].price.toNumber(), 16);
+    assert.equal(bids[0].amount.toNumber(), 1);
+  });
+
+  it("Pegged order - rests with its limit and escrows at that limit", async () => {
+    // 1 whole base token, never bidding above 1000
+    const amount = new anchor.BN(10 ** 9);
+    const limit = new anchor.BN(1000);
+    const clientOrderId = new anchor.BN(81);
+
+    const traderQuoteBefore = await provider.connection.getTokenAccountBalance(
+      trader_quote_mint_acc
+    );
+
+    await program.methods
+      .placeOrder(
+        limit,
+        amount,
+        { bid: {} },
+        goodTillCancel,
+        notPostOnly,
+        noExpiry,
+        clientOrderId,
+        cancelTaker,
+        noDisplay,
+        noLink,
+        { bestBid: {} },
+        new anchor.BN(-1)
+      )
+      .accounts({
+        trader: trader.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const orderBook = await fetchOrderBook();
+    const pegged = orderBook.bids.find((bid) =>
+      bid.clientOrderId.eq(clientOrderId)
+    );
+    // Stored as the index of the `Peg` variant
+    assert.equal(pegged.peg, 1);
+    assert.equal(pegged.pegOffset, -1);
+    assert.equal(pegged.price.toNumber(), 1000);
+
+    const traderQuoteEscrowed =
+      await provider.connection.getTokenAccountBalance(trader_quote_mint_acc);
+    assert.equal(
+      Number(traderQuoteBefore.value.amount) -
+        Number(traderQuoteEscrowed.value.amount),
+      1000
+    );
+
+    await program.methods
+      .cancelOrderByClientId(clientOrderId)
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: marketPda,
+        bids: bidsPda,
+        asks: asksPda,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        quoteMintVault: quote_mint_valut,
+        baseMintVault: base_mint_valut,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const traderQuoteAfter = await provider.connection.getTokenAccountBalance(
+      trader_quote_mint_acc
+    );
+    assert.equal(traderQuoteAfter.value.amount, traderQuoteBefore.value.amount);
+  });
+
+  it("Pro-rata matching - shares a fill across a level by size", async () => {
+    const testMarket = await createTestMarket({
+      proRata: { topOrderPriority: true },
+    });
+    for (const amount of [2, 3, 5]) {
+      await placeOn(testMarket, maker, { ask: {} }, 10, amount).rpc();
+    }
+    const level = (await fetchSide(testMarket.asks)).orders;
+    const makerQuoteBefore = await balance(maker_quote_mint_acc);
+    const traderBaseBefore = await balance(testMarket.traderBase);
+
+    await placeOn(testMarket, trader, { bid: {} }, 10, 7)
+      .remainingAccounts([writable(maker_quote_mint_acc)])
+      .rpc();
+
+    // The first order fills in full, 2. The other 5 is shared 3 : 5, which rounds down to
+    // 1 and 3, and the unit left over goes to the earliest order that still has room
+    const asks = (await fetchSide(testMarket.asks)).orders;
+    assert.deepEqual(
+      asks.map((ask) => ask.orderId.toNumber()),
+      [level[1].orderId.toNumber(), level[2].orderId.toNumber()]
+    );
+    assert.deepEqual(
+      asks.map((ask) => ask.amount.toNumber()),
+      [3 - 2, 5 - 3]
+    );
+    assert.equal(await balance(testMarket.traderBase), traderBaseBefore + 7);
+    assert.equal(await balance(maker_quote_mint_acc), makerQuoteBefore + 7 * 10);
+  });
+
+  it("Pro-rata matching - the crank shares a crossing post across a level", async () => {
+    const testMarket = await createTestMarket({
+      proRata: { topOrderPriority: false },
+    });
+    await postOn(testMarket, maker, { ask: {} }, 10, 5).rpc();
+    await postOn(testMarket, maker, { ask: {} }, 10, 5).rpc();
+    await postOn(testMarket, trader, { bid: {} }, 10, 4).rpc();
+    const traderBaseBefore = await balance(testMarket.traderBase);
+
+    await crankOn(testMarket);
+
+    // The later bid is the taker; first in, first out would fill only the first ask
+    const asks = (await fetchSide(testMarket.asks)).orders;
+    assert.deepEqual(asks.map((ask) => ask.amount.toNumber()), [3, 3]);
+    assert.equal((await fetchSide(testMarket.bids)).orders.length, 0);
+    assert.equal(await balance(testMarket.traderBase), traderBaseBefore + 4);
+  });
+
+  it("Place order - keeps the book sorted by price, then arrival", async () => {
+    // Far above every bid, the lower ask arrives second but must come first
+    const prices = [new anchor.BN(10 ** 6 + 2), new anchor.BN(10 ** 6 + 1)];
+    const clientOrderIds = [new anchor.BN(91), new anchor.BN(92)];
+
+    for (const [i, price] of prices.entries()) {
+      await program.methods
+        .placeOrder(
+          price,
+          new anchor.BN(1),
+          { ask: {} },
+          goodTillCancel,
+          notPostOnly,
+          noExpiry,
+          clientOrderIds[i],
+          cancelTaker,
+          noDisplay,
+          noLink,
+          notPegged,
+          noPegOffset
+        )
+        .accounts({
+          trader: trader.publicKey,
+          baseMint: base_mint,
+          quoteMint: quote_mint,
+          traderBaseMintAccount: trader_base_mint_acc,
+          traderQuoteMintAccount: trader_quote_mint_acc,
+          feeCollector: fee_collector_acc,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([trader])
+        .rpc();
+    }
+
+    const orderBook = await fetchOrderBook();
+    for (let i = 1; i < orderBook.asks.length; i++) {
+      assert.ok(orderBook.asks[i - 1].price.lte(orderBook.asks[i].price));
+    }
+    for (let i = 1; i < orderBook.bids.length; i++) {
+      assert.ok(orderBook.bids[i - 1].price.gte(orderBook.bids[i].price));
+    }
+    const position = (clientOrderId) =>
+      orderBook.asks.findIndex((ask) => ask.clientOrderId.eq(clientOrderId));
+    assert.ok(position(clientOrderIds[1]) < position(clientOrderIds[0]));
+
+    for (const clientOrderId of clientOrderIds) {
+      await program.methods
+        .cancelOrderByClientId(clientOrderId)
+        .accountsPartial({
+          trader: trader.publicKey,
+          market: marketPda,
+          bids: bidsPda,
+          asks: asksPda,
+          baseMint: base_mint,
+          quoteMint: quote_mint,
+          quoteMintVault: quote_mint_valut,
+          baseMintVault: base_mint_valut,
+          traderBaseMintAccount: trader_base_mint_acc,
+          traderQuoteMintAccount: trader_quote_mint_acc,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([trader])
+        .rpc();
+    }
+  });
+
+  it("Post order - rests an ask locking only the asks side", async () => {
+    // Far above every bid, so it does not cross
+    const price = new anchor.BN(10 ** 6 + 5);
+    const amount = new anchor.BN(1);
+
+    const orderBookBefore = await fetchOrderBook();
+    const traderBaseBefore = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+
+    // Neither the bids nor the quote vault are part of the instruction
+    const postAsk = program.methods
+      .postOrder({ ask: {} }, price, amount, noExpiry)
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: marketPda,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        bookSide: asksPda,
+        vault: base_mint_valut,
+        traderTokenAccount: trader_base_mint_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader]);
+    const instruction = await postAsk.instruction();
+    const lock = (pubkey) =>
+      instruction.keys.find((key) => key.pubkey.equals(pubkey))?.isWritable;
+    assert.equal(lock(asksPda), true);
+    assert.equal(lock(bidsPda), undefined);
+    assert.equal(lock(quote_mint_valut), undefined);
+    await postAsk.rpc();
+
+    const orderBookAfter = await fetchOrderBook();
+    // Asks take the odd ids, bids the even ones
+    const orderId = orderBookBefore.nextAskId;
+    assert.equal(orderId.toNumber() % 2, 1);
+    const posted = orderBookAfter.asks.find((ask) => ask.orderId.eq(orderId));
+    assert.equal(posted.price.toNumber(), price.toNumber());
+    assert.equal(posted.clientOrderId.toNumber(), 0);
+    assert.equal(orderBookAfter.bids.length, orderBookBefore.bids.length);
+    assert.equal(
+      orderBookAfter.nextBidId.toNumber(),
+      orderBookBefore.nextBidId.toNumber()
+    );
+
+    const traderBaseEscrowed = await provider.connection.getTokenAccountBalance(
+      trader_base_mint_acc
+    );
+    assert.equal(
+      Number(traderBaseBefore.value.amount) -
+        Number(traderBaseEscrowed.value.amount),
+      1
+    );
+
+    await program.methods
+      .cancelOrder({ ask: {} }, orderId)
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: marketPda,
+        bids: null,
+        asks: asksPda,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        quoteMintVault: null,
+        baseMintVault: base_mint_valut,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+  });
+
+  it("Post order - a crossing post rests until the crank fills it", async () => {
+    const testMarket = await createTestMarket({ fifo: {} });
+
+    // Neither post sees the other side, so the ask rests below the bid
+    await postOn(testMarket, maker, { bid: {} }, 10, 2).rpc();
+    await postOn(testMarket, trader, { ask: {} }, 9, 2).rpc();
+    assert.equal((await fetchSide(testMarket.bids)).orders.length, 1);
+    assert.equal((await fetchSide(testMarket.asks)).orders.length, 1);
+
+    const makerBaseBefore = await balance(testMarket.makerBase);
+    const traderQuoteBefore = await balance(trader_quote_mint_acc);
+    const makerQuoteBefore = await balance(maker_quote_mint_acc);
+
+    await crankOn(testMarket);
+
+    assert.equal((await fetchSide(testMarket.bids)).orders.length, 0);
+    assert.equal((await fetchSide(testMarket.asks)).orders.length, 0);
+    // Filled at the ask's 9, the bid escrowed at 10 gets the difference back
+    assert.equal(await balance(testMarket.makerBase), makerBaseBefore + 2);
+    assert.equal(await balance(trader_quote_mint_acc), traderQuoteBefore + 18);
+    assert.equal(await balance(maker_quote_mint_acc), makerQuoteBefore + 2);
+  });
+
+  it("Self-trade prevention - the crank cancels the later of two crossing posts", async () => {
+    const testMarket = await createTestMarket({ fifo: {} });
+
+    // The bid is the older order on the book, though asks count ids from 1 and bids from 2
+    await postOn(testMarket, trader, { bid: {} }, 10, 2).rpc();
+    await postOn(testMarket, trader, { ask: {} }, 9, 3).rpc();
+    const traderBaseBefore = await balance(testMarket.traderBase);
+
+    await crankOn(testMarket);
+
+    // Posts cancel the taker: the ask, refunded in full
+    const bids = (await fetchSide(testMarket.bids)).orders;
+    assert.equal((await fetchSide(testMarket.asks)).orders.length, 0);
+    assert.deepEqual(
+      bids.map((bid) => [bid.price.toNumber(), bid.amount.toNumber()]),
+      [[10, 2]]
+    );
+    assert.equal(await balance(testMarket.traderBase), traderBaseBefore + 3);
+  });
+
+  it("Self-trade prevention - the crank counts the ask as later within one slot", async () => {
+    const testMarket = await createTestMarket({ fifo: {} });
+
+    // One transaction, so one slot: the crossing bid is the second on its side and the ask
+    // the first on its own, but neither is later than the other
+    const tx = new anchor.web3.Transaction().add(
+      await postOn(testMarket, trader, { bid: {} }, 5, 1).instruction(),
+      await postOn(testMarket, trader, { ask: {} }, 9, 3).instruction(),
+      await postOn(testMarket, trader, { bid: {} }, 10, 2).instruction()
+    );
+    await provider.sendAndConfirm(tx, [trader]);
+
+    await crankOn(testMarket);
+
+    const bids = (await fetchSide(testMarket.bids)).orders;
+    assert.equal((await fetchSide(testMarket.asks)).orders.length, 0);
+    assert.deepEqual(
+      bids.map((bid) => [bid.price.toNumber(), bid.amount.toNumber()]),
+      [
+        [10, 2],
+        [5, 1],
+      ]
+    );
+  });
+
+  it("Quote market order - refunds the budget it could not spend", async () => {
+    // No ask is priced as low as 1, so none of the budget can be spent
+    const quoteBudget = new anchor.BN(100);
+    const worstPrice = new anchor.BN(1);
+
+    const orderBookBefore = await fetchOrderBook();
+    const traderQuoteBefore = await provider.connection.getTokenAccountBalance(
+      trader_quote_mint_acc
+    );
+
+    await program.methods
+      .placeQuoteMarketOrder(quoteBudget, worstPrice, cancelTaker)
+      .accounts({
+        trader: trader.publicKey,
+        baseMint: base_mint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: trader_base_mint_acc,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+
+    const orderBookAfter = await fetchOrderBook();
+    const traderQuoteAfter = await provider.connection.getTokenAccountBalance(
+      trader_quote_mint_acc
+    );
+
+    assert.equal(orderBookAfter.asks.length, orderBookBefore.asks.length);
+    assert.equal(traderQuoteAfter.value.amount, traderQuoteBefore.value.amount);
+  });
+
+  it("Quote market order - buys across levels and refunds the dust", async () => {
+    const testMarket = await createTestMarket({ fifo: {} });
+    await placeOn(testMarket, maker, { ask: {} }, 10, 2).rpc();
+    await placeOn(testMarket, maker, { ask: {} }, 11, 3).rpc();
+    const traderBaseBefore = await balance(testMarket.traderBase);
+    const traderQuoteBefore = await balance(trader_quote_mint_acc);
+
+    // 45 buys 2 at 10, then 2 at 11 with the 25 left; the last 3 cannot buy another unit
+    await program.methods
+      .placeQuoteMarketOrder(new anchor.BN(45), new anchor.BN(12), cancelTaker)
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: testMarket.market,
+        bids: testMarket.bids,
+        asks: testMarket.asks,
+        triggerOrders: testMarket.triggerOrders,
+        baseMint: testMarket.baseMint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: testMarket.traderBase,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([writable(maker_quote_mint_acc)])
+      .signers([trader])
+      .rpc();
+
+    const asks = (await fetchSide(testMarket.asks)).orders;
+    assert.deepEqual(
+      asks.map((ask) => [ask.price.toNumber(), ask.amount.toNumber()]),
+      [[11, 1]]
+    );
+    assert.equal(await balance(testMarket.traderBase), traderBaseBefore + 4);
+    assert.equal(
+      await balance(trader_quote_mint_acc),
+      traderQuoteBefore - (2 * 10 + 2 * 11)
+    );
+  });
+
+  it("Swap - fails when the output is below the minimum", async () => {
+    // No bid comes close to paying this much for one base unit
+    const amountIn = new anchor.BN(1);
+    const minAmountOut = new anchor.BN(10 ** 12);
+
+    try {
+      await program.methods
+        .swap(amountIn, minAmountOut, { baseToQuote: {} })
+        .accounts({
+          trader: trader.publicKey,
+          baseMint: base_mint,
+          quoteMint: quote_mint,
+          traderBaseMintAccount: trader_base_mint_acc,
+          traderQuoteMintAccount: trader_quote_mint_acc,
+          feeCollector: fee_collector_acc,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([trader])
+        .rpc();
+      assert.fail("Should have failed with SlippageExceeded");
+    } catch (error) {
+      assert.equal(error.error.errorCode.code, "SlippageExceeded");
+    }
+  });
+
+  // Swaps `amountIn` on a test market and returns how the trader's balances moved
+  const swapOn = async (
+    testMarket,
+    amountIn: number,
+    minAmountOut: number,
+    direction,
+    remainingAccounts
+  ) => {
+    const baseBefore = await balance(testMarket.traderBase);
+    const quoteBefore = await balance(trader_quote_mint_acc);
+
+    await program.methods
+      .swap(new anchor.BN(amountIn), new anchor.BN(minAmountOut), direction)
+      .accountsPartial({
+        trader: trader.publicKey,
+        market: testMarket.market,
+        bids: testMarket.bids,
+        asks: testMarket.asks,
+        triggerOrders: testMarket.triggerOrders,
+        baseMint: testMarket.baseMint,
+        quoteMint: quote_mint,
+        traderBaseMintAccount: testMarket.traderBase,
+        traderQuoteMintAccount: trader_quote_mint_acc,
+        feeCollector: fee_collector_acc,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts(remainingAccounts.map(writable))
+      .signers([trader])
+      .rpc();
+
+    return {
+      baseMoved: (await balance(testMarket.traderBase)) - baseBefore,
+      quoteMoved: (await balance(trader_quote_mint_acc)) - quoteBefore,
+    };
+  };
+
+  it("Swap - quote to base pays out the base and refunds unspent quote", async () => {
+    const testMarket = await createTestMarket({ fifo: {} });
+    await placeOn(testMarket, maker, { ask: {} }, 10, 2).rpc();
+
+    // 25 quote buys all 2 on offer for 20
+    const { baseMoved, quoteMoved } = await swapOn(
+      testMarket,
+      25,
+      2,
+      { quoteToBase: {} },
+      [maker_quote_mint_acc]
+    );
+
+    assert.equal(baseMoved, 2);
+    assert.equal(quoteMoved, -20);
+    assert.equal((await fetchSide(testMarket.asks)).orders.length, 0);
+  });
+
+  it("Swap - base to quote pays out the quote and refunds unspent base", async () => {
+    const testMarket = await createTestMarket({ fifo: {} });
+    await placeOn(testMarket, maker, { bid: {} }, 10, 2).rpc();
+
+    // Of 5 base only the 2 bid for sell, for 20
+    const { baseMoved, quoteMoved } = await swapOn(
+      testMarket,
+      5,
+      20,
+      { baseToQuote: {} },
+      [testMarket.makerBase]
+    );
+
+    assert.equal(baseMoved, -2);
+    assert.equal(quoteMoved, 20);
+    assert.equal((await fetchSide(testMarket.bids)).orders.length, 0);
+  });
 });